@@ -1,23 +1,31 @@
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
-use std::ops::Not;
+use std::ops::Range;
 use std::str::FromStr;
-use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use clap::builder::PossibleValue;
-use clap::{Arg, Command};
-use crossterm::cursor::MoveToColumn;
-use crossterm::style::{Print, PrintStyledContent, Stylize};
-use crossterm::terminal::{Clear, ClearType};
-use crossterm::{execute, queue};
-use gerlib::changes::{AdditionalOpt, ChangeEndpoints, ChangeInfo, QueryParams, QueryStr};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use crossterm::style::{Color, Print, StyledContent, Stylize};
+use crossterm::{execute, queue, terminal};
+use gerlib::accounts::{AccountEndpoints, AccountInfo};
+use gerlib::changes::{
+    AbandonInput, AdditionalOpt, ChangeEndpoints, ChangeInfo, ChangeStatus, CommentInfo, FileInfo,
+    QueryParams, QueryStr, RebaseInput, RestoreInput, ReviewInput, ReviewerInput, ReviewerState,
+    RevisionInfo, SubmitInput, TopicInput,
+};
 use gerlib::GerritRestApi;
 use once_cell::sync::Lazy;
 use parking_lot::ReentrantMutex;
+use unicode_width::UnicodeWidthStr;
 
-use crate::cli::SmartNewLine;
-use crate::util::CmdAction;
-use crate::{cli, cliprintln, print_help, util};
+use crate::cli::{PrintStyled, SmartNewLine};
+use crate::config::{load_config, save_config, Config};
+use crate::highlight::Highlighter;
+use crate::util::{CmdAction, CmdError};
+use crate::{cli, cliprintln, command_help, print_help, remote, theme, util};
 
 static CHANGE_CONTEXT: Lazy<ReentrantMutex<RefCell<ChangeContext>>> =
     Lazy::new(|| ReentrantMutex::new(RefCell::new(ChangeContext::default())));
@@ -25,6 +33,12 @@ static CHANGE_CONTEXT: Lazy<ReentrantMutex<RefCell<ChangeContext>>> =
 #[derive(Default)]
 struct ChangeContext {
     list: Vec<ChangeInfo>,
+    /// position in `list` of the change shown last, for `next` and `prev`
+    current: Option<usize>,
+    /// paths of the files listed last with `files`, referenced by `$index` in `diff`
+    files: Vec<String>,
+    /// colors of statuses replacing the default ones, indexed by status
+    status_colors: HashMap<String, Color>,
 }
 
 /// Get the `change` command model/schema as a Clap command structure
@@ -35,173 +49,2649 @@ pub fn command() -> Command {
         .disable_help_subcommand(true)
         .about("Change commands")
         .subcommands([
-            Command::new("show")
-                .arg(Arg::new("ID").required(true))
-                .about("Display change info"),
+            command_show(),
             command_query(),
-            Command::new("help").alias("?").about("Print command help"),
+            command_review(),
+            command_comment(),
+            command_topic(),
+            command_abandon(),
+            command_restore(),
+            command_submit(),
+            command_rebase(),
+            command_add_reviewer(),
+            command_remove_reviewer(),
+            command_files(),
+            command_diff(),
+            command_open(),
+            command_copy(),
+            command_star(),
+            command_unstar(),
+            command_checkout(),
+            Command::new("next").about("Show the next change from the last query"),
+            Command::new("prev").about("Show the previous change from the last query"),
+            command_help(),
             Command::new("exit").about("Exit from current mode"),
             Command::new("quit").about("Quit the program"),
         ])
 }
 
+pub fn command_show() -> Command {
+    Command::new("show")
+        .about("Display change info")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("files")
+                .long("files")
+                .action(ArgAction::SetTrue)
+                .help("Display the files changed in the current revision"),
+        )
+        .arg(
+            Arg::new("comments")
+                .long("comments")
+                .action(ArgAction::SetTrue)
+                .help("Display the comments of the change"),
+        )
+        .arg(patchset_arg())
+        .arg(json_arg())
+}
+
 pub fn command_query() -> Command {
-    Command::new("query").about("Query changes").arg(
-        Arg::new("QUERY").num_args(0..).last(true).value_parser([
-            PossibleValue::new("owner:self"),
-            PossibleValue::new("is:open"),
-            PossibleValue::new("is:wip"),
-            PossibleValue::new("-owner:self"),
-            PossibleValue::new("-is:open"),
-            PossibleValue::new("-is:wip"),
-        ]),
-    )
+    Command::new("query")
+        .about("Query changes")
+        // terms may be negated with a leading hyphen, like -is:wip,
+        // so the options after them are taken as terms here and parsed by hand
+        .arg(Arg::new("QUERY").num_args(0..).allow_hyphen_values(true))
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_name("N")
+                .help("Maximum number of changes to list"),
+        )
+        .arg(
+            Arg::new("start")
+                .long("start")
+                .value_name("N")
+                .help("Number of changes to skip"),
+        )
+        .arg(
+            Arg::new("save")
+                .long("save")
+                .value_name("NAME")
+                .help("Save the query under a name, instead of running it"),
+        )
+        .arg(
+            Arg::new("run")
+                .long("run")
+                .value_name("NAME")
+                .help("Run a saved query"),
+        )
+        .arg(
+            Arg::new("project")
+                .long("project")
+                .value_name("NAME")
+                .help("Only changes of a project, as the query term project:NAME"),
+        )
+        .arg(
+            Arg::new("branch")
+                .long("branch")
+                .value_name("NAME")
+                .help("Only changes targeting a branch, as the query term branch:NAME"),
+        )
+        .arg(
+            Arg::new("status")
+                .long("status")
+                .value_name("STATUS")
+                .value_parser(["open", "merged", "abandoned"])
+                .help("Only changes with a status, as the query term status:STATUS"),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .value_name("FIELD")
+                .value_parser(["updated", "number", "status", "subject"])
+                .help("Sort the changes by a field, instead of server order"),
+        )
+        .arg(
+            Arg::new("reverse")
+                .long("reverse")
+                .action(ArgAction::SetTrue)
+                .help("Reverse the sort order"),
+        )
+        .arg(
+            Arg::new("wrap")
+                .long("wrap")
+                .action(ArgAction::SetTrue)
+                .help("Wrap long subjects into lines below, instead of truncating them"),
+        )
+        .arg(
+            Arg::new("owner")
+                .long("owner")
+                .action(ArgAction::SetTrue)
+                .help("Show the owner of each change"),
+        )
+        .arg(
+            Arg::new("age")
+                .long("age")
+                .action(ArgAction::SetTrue)
+                .help("Show how long ago each change was updated"),
+        )
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .action(ArgAction::SetTrue)
+                .help("List saved queries"),
+        )
+        .arg(json_arg())
+}
+
+pub fn command_review() -> Command {
+    Command::new("review")
+        .about("Review the current patchset of a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("code-review")
+                .long("code-review")
+                .value_name("VOTE")
+                .allow_negative_numbers(true)
+                .value_parser(value_parser!(i32).range(-2..=2))
+                .help("Code-Review vote from -2 to +2"),
+        )
+        .arg(
+            Arg::new("verified")
+                .long("verified")
+                .value_name("VOTE")
+                .allow_negative_numbers(true)
+                .value_parser(value_parser!(i32).range(-1..=1))
+                .help("Verified vote from -1 to +1"),
+        )
+        .arg(message_arg())
+        .arg(edit_arg().conflicts_with("message"))
+        .arg(editor_arg().conflicts_with_all(["message", "edit"]))
+}
+
+pub fn command_comment() -> Command {
+    Command::new("comment")
+        .about("Post a message on a change, without votes")
+        .arg(Arg::new("ID").required(true))
+        .arg(Arg::new("MESSAGE").num_args(1..))
+        .arg(
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .value_name("FILE")
+                .conflicts_with("MESSAGE")
+                .help("Read the message from a file"),
+        )
+        .arg(edit_arg().conflicts_with_all(["MESSAGE", "file"]))
+        .arg(editor_arg().conflicts_with_all(["MESSAGE", "file", "edit"]))
+}
+
+pub fn command_topic() -> Command {
+    Command::new("topic")
+        .about("Display or set the topic of a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(Arg::new("TOPIC"))
+        .arg(
+            Arg::new("delete")
+                .long("delete")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("TOPIC")
+                .help("Remove the topic of the change"),
+        )
+        .arg(
+            Arg::new("editor")
+                .long("editor")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["TOPIC", "delete"])
+                .help("Edit the topic in $EDITOR, removing it if left empty"),
+        )
+}
+
+pub fn command_abandon() -> Command {
+    Command::new("abandon")
+        .about("Abandon a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(message_arg())
+        .arg(yes_arg())
+}
+
+pub fn command_restore() -> Command {
+    Command::new("restore")
+        .about("Restore an abandoned change")
+        .arg(Arg::new("ID").required(true))
+        .arg(message_arg())
+}
+
+pub fn command_submit() -> Command {
+    Command::new("submit")
+        .about("Submit a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Attempt to submit even if the change is not submittable"),
+        )
+        .arg(yes_arg())
+}
+
+pub fn command_rebase() -> Command {
+    Command::new("rebase")
+        .about("Rebase a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("base")
+                .long("base")
+                .value_name("BASE")
+                .help("Change or revision to rebase onto, instead of the target branch"),
+        )
+}
+
+pub fn command_add_reviewer() -> Command {
+    Command::new("add-reviewer")
+        .about("Add a reviewer to a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(Arg::new("ACCOUNT").required(true))
+        .arg(
+            Arg::new("cc")
+                .long("cc")
+                .action(ArgAction::SetTrue)
+                .help("Add as CC instead of reviewer"),
+        )
+}
+
+pub fn command_remove_reviewer() -> Command {
+    Command::new("remove-reviewer")
+        .about("Remove a reviewer from a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(Arg::new("ACCOUNT").required(true))
+}
+
+pub fn command_files() -> Command {
+    Command::new("files")
+        .about("List the files of the current revision of a change")
+        .arg(Arg::new("ID").required(true))
+}
+
+pub fn command_diff() -> Command {
+    Command::new("diff")
+        .about("Display the diff of a file in a revision of a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(Arg::new("FILE").required(true))
+        .arg(
+            Arg::new("highlight")
+                .long("highlight")
+                .action(ArgAction::SetTrue)
+                .help("Highlight the syntax of the file content, by its extension"),
+        )
+        .arg(patchset_arg())
+}
+
+pub fn command_open() -> Command {
+    Command::new("open")
+        .about("Open a change in the web browser")
+        .arg(Arg::new("ID").required(true))
+}
+
+pub fn command_star() -> Command {
+    Command::new("star")
+        .about("Star a change, to find it later with is:starred")
+        .arg(Arg::new("ID").required(true))
+}
+
+pub fn command_unstar() -> Command {
+    Command::new("unstar")
+        .about("Remove the star of a change")
+        .arg(Arg::new("ID").required(true))
+}
+
+pub fn command_checkout() -> Command {
+    Command::new("checkout")
+        .about("Print the git commands to fetch and check out the current revision of a change")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("scheme")
+                .long("scheme")
+                .value_name("SCHEME")
+                .value_parser(["http", "ssh", "anonymous http"])
+                .default_value("http")
+                .help("Protocol to fetch the revision with"),
+        )
+        .arg(
+            Arg::new("run")
+                .long("run")
+                .action(ArgAction::SetTrue)
+                .help("Run the git commands in the current directory, instead of printing them"),
+        )
+}
+
+pub fn command_copy() -> Command {
+    Command::new("copy")
+        .about("Copy the web URL or the Change-Id of a change to the clipboard")
+        .arg(Arg::new("ID").required(true))
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("id")
+                .help("Copy the web URL of the change (default)"),
+        )
+        .arg(
+            Arg::new("id")
+                .long("id")
+                .action(ArgAction::SetTrue)
+                .help("Copy the Change-Id of the change"),
+        )
+}
+
+/// Flag of commands that can print out JSON instead of styled text, for scripting.
+fn json_arg() -> Arg {
+    Arg::new("json")
+        .long("json")
+        .action(ArgAction::SetTrue)
+        .help("Print out JSON for scripting")
+}
+
+/// Option of commands that display a revision, to select it by patchset instead of the current one.
+fn patchset_arg() -> Arg {
+    Arg::new("patchset")
+        .long("patchset")
+        .value_name("N")
+        .value_parser(value_parser!(u32))
+        .help("Patchset number to display, by default the current one")
+}
+
+/// Flag of destructive commands to skip asking for confirmation.
+fn yes_arg() -> Arg {
+    Arg::new("yes")
+        .short('y')
+        .long("yes")
+        .action(ArgAction::SetTrue)
+        .help("Do not ask for confirmation")
+}
+
+/// Optional message argument of commands that act on a change.
+/// The message can be quoted, or given unquoted as all words until the next option,
+/// which are joined with spaces, see [`get_message`].
+fn message_arg() -> Arg {
+    Arg::new("message")
+        .short('m')
+        .long("message")
+        .num_args(1..)
+        .help("Message to post on the change")
+}
+
+/// Flag to compose the message of commands that post one in lines of text,
+/// for messages too long for a single line of the prompt.
+fn edit_arg() -> Arg {
+    Arg::new("edit")
+        .short('e')
+        .long("edit")
+        .action(ArgAction::SetTrue)
+        .help("Compose the message in multiple lines")
+}
+
+/// Flag to compose the message of commands that post one in the editor of the user.
+fn editor_arg() -> Arg {
+    Arg::new("editor")
+        .long("editor")
+        .action(ArgAction::SetTrue)
+        .help("Compose the message in $EDITOR")
+}
+
+/// Get common search operators as completion values of the free-form `QUERY` argument.
+pub fn query_presets() -> Vec<PossibleValue> {
+    [
+        "owner:self",
+        "is:open",
+        "is:wip",
+        "is:starred",
+        "-owner:self",
+        "-is:open",
+        "-is:wip",
+        "-is:starred",
+    ]
+    .into_iter()
+    .map(PossibleValue::new)
+    .collect()
+}
+
+/// Get the projects of the changes cached from the last query as completion values
+/// of `query --project`.
+pub fn cached_project_values() -> Vec<PossibleValue> {
+    cached_change_field_values(|change| &change.project)
+}
+
+/// Get the branches of the changes cached from the last query as completion values
+/// of `query --branch`.
+pub fn cached_branch_values() -> Vec<PossibleValue> {
+    cached_change_field_values(|change| &change.branch)
+}
+
+/// Get the distinct values of a field of the changes cached from the last query, sorted.
+fn cached_change_field_values(field: impl Fn(&ChangeInfo) -> &String) -> Vec<PossibleValue> {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    let mut values: Vec<&String> = ctx.list.iter().map(field).collect();
+    values.sort();
+    values.dedup();
+    values.into_iter().map(PossibleValue::new).collect()
+}
+
+/// Get the names of the saved queries as completion values of `query --run`.
+pub fn saved_query_names() -> Vec<PossibleValue> {
+    let config = Config::load().unwrap_or_default();
+    config
+        .queries
+        .into_iter()
+        .map(|(name, query)| PossibleValue::new(name).help(query))
+        .collect()
+}
+
+/// Get the changes cached from the last query as completion values of the `ID` argument,
+/// by number and by `$index`, described by their subject.
+pub fn cached_change_values() -> Vec<PossibleValue> {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    ctx.list
+        .iter()
+        .enumerate()
+        .map(|(i, change)| {
+            PossibleValue::new(change.number.to_string())
+                .alias(format!("${}", i + 1))
+                .help(change.subject.clone())
+        })
+        .collect()
+}
+
+/// Get the files listed last with `files` as completion values of the `FILE` argument,
+/// by path and by `$index`.
+pub fn cached_file_values() -> Vec<PossibleValue> {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    ctx.files
+        .iter()
+        .enumerate()
+        .map(|(i, path)| PossibleValue::new(path.clone()).alias(format!("${}", i + 1)))
+        .collect()
 }
 
 /// Handle `change` command.
-pub fn run_command(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, ()> {
+pub fn run_command(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
     let mut writer = cli::stdout();
     if args.is_empty() {
-        return Ok(CmdAction::EnterMode("gerrit change".to_string()));
+        query_on_enter(gerrit);
+        return Ok(CmdAction::EnterMode("change".to_string()));
     }
     let (cmd, cmd_args) = args.split_first().unwrap();
     match cmd.as_str() {
         "show" => show_change(cmd_args, gerrit),
         "query" => query_changes(cmd_args, gerrit),
-        "help" | "?" => {
-            print_help(&mut writer, &command());
+        "review" => review_change(cmd_args, gerrit),
+        "comment" => comment_change(cmd_args, gerrit),
+        "topic" => topic_change(cmd_args, gerrit),
+        "abandon" => abandon_change(cmd_args, gerrit),
+        "restore" => restore_change(cmd_args, gerrit),
+        "submit" => submit_change(cmd_args, gerrit),
+        "rebase" => rebase_change(cmd_args, gerrit),
+        "add-reviewer" => add_reviewer(cmd_args, gerrit),
+        "remove-reviewer" => remove_reviewer(cmd_args, gerrit),
+        "files" => list_files(cmd_args, gerrit),
+        "diff" => diff_file(cmd_args, gerrit),
+        "open" => open_change(cmd_args),
+        "copy" => copy_change(cmd_args, gerrit),
+        "checkout" => checkout_change(cmd_args, gerrit),
+        "star" => star_change(cmd_args, true, gerrit),
+        "unstar" => star_change(cmd_args, false, gerrit),
+        "next" => show_adjacent_change(true, gerrit),
+        "prev" => show_adjacent_change(false, gerrit),
+        "help" => {
+            print_help(&mut writer, &command(), cmd_args);
             Ok(CmdAction::Ok)
         }
-        "exit" => Ok(CmdAction::Ok),
-        _ => Err(()),
+        "exit" => Ok(CmdAction::ExitMode),
+        _ => Err(CmdError::Unhandled),
     }
 }
 
 /// Print out a list of changes from search query.
-pub fn query_changes(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, ()> {
+pub fn query_changes(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
     let mut writer = cli::stdout();
 
+    let QueryArgs {
+        words,
+        limit,
+        start,
+        save,
+        run,
+        list,
+        sort,
+        reverse,
+        wrap,
+        columns,
+        json,
+    } = QueryArgs::parse(args)?;
+
+    if list {
+        return list_saved_queries();
+    }
+    if let Some(name) = save {
+        return save_query(&name, &words);
+    }
+
+    let query = if let Some(name) = run {
+        let config = load_config()?;
+        let Some(saved_query) = config.queries.get(&name) else {
+            return Err(CmdError::Failed(format!("no saved query '{}'", name)));
+        };
+        // extra terms narrow down the saved query
+        Some(
+            [saved_query.clone()]
+                .into_iter()
+                .chain(words)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    } else if words.is_empty() {
+        // without a query given, fall back to the default query from config
+        Config::load().ok().and_then(|config| config.default_query)
+    } else {
+        Some(words.join(" "))
+    };
     let query_param = QueryParams {
-        search_queries: args
-            .is_empty()
-            .not()
-            .then(|| vec![QueryStr::Raw(args.join(" "))]),
+        search_queries: query.map(|query| vec![QueryStr::Raw(query)]),
         additional_opts: Some(vec![
             AdditionalOpt::DetailedAccounts,
             AdditionalOpt::CurrentRevision,
         ]),
-        limit: None,
-        start: None,
+        limit,
+        start,
+    };
+    let loading = util::loading_if(!json);
+    let changes_list =
+        remote::run_request(gerrit, move |gerrit| gerrit.query_changes(&query_param));
+    loading.stop();
+    let mut changes = flatten_batches(changes_list?);
+
+    // the server marks the last change when the limit cut off more results
+    let more_changes = changes
+        .last()
+        .is_some_and(|change| change.more_changes.unwrap_or(false));
+    if let Some(sort) = sort {
+        sort_changes(&mut changes, sort, reverse);
+    }
+
+    if json {
+        util::print_json(&mut writer, &changes)?;
+    } else {
+        print_changes(
+            &mut writer,
+            &changes,
+            more_changes,
+            start.unwrap_or(0),
+            wrap,
+            columns,
+        );
+    }
+
+    // the indexes printed refer to the cached list, so it's kept in display order
+    cache_changes(changes);
+
+    Ok(CmdAction::Ok)
+}
+
+/// Run the default query when entering the change mode, if enabled in configuration,
+/// so the changes listed can be referenced by `$index` right away.
+/// A failed query is reported but doesn't keep the mode from being entered.
+fn query_on_enter(gerrit: &mut GerritRestApi) {
+    let enabled = Config::load().is_ok_and(|config| {
+        config.query_on_enter && config.default_query.is_some_and(|query| !query.is_empty())
+    });
+    if !enabled {
+        return;
+    }
+    if let Err(CmdError::Failed(message)) = query_changes(&[], gerrit) {
+        let mut writer = cli::stdout();
+        cliprintln!(
+            writer,
+            "{}",
+            theme::failure(format!("default query failed: {}", message))
+        )
+        .unwrap();
+    }
+}
+
+/// Join the batches of changes returned for each search query into a single list.
+/// Changes are indexed by their position in this list, running across batches,
+/// both when printed and when referenced by `$index`.
+fn flatten_batches(batches: Vec<Vec<ChangeInfo>>) -> Vec<ChangeInfo> {
+    batches.into_iter().flatten().collect()
+}
+
+/// Cache a list of changes to be referenced by `$index` in following commands.
+pub fn cache_changes(changes: Vec<ChangeInfo>) {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.list = changes;
+    ctx.current = None;
+}
+
+/// Open the web page of a change in the system browser.
+/// The URL is printed instead when not in the interactive shell or if no browser could be opened.
+fn open_change(args: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_open(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let url = remote::change_url(&id);
+    if !cli::is_interactive() {
+        cliprintln!(writer, "{}", url).unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    if let Err(err) = open::that(&url) {
+        cliprintln!(writer, "Could not open a browser ({}), go to: {}", err, url).unwrap();
+    }
+    Ok(CmdAction::Ok)
+}
+
+/// Copy the web URL of a change to the clipboard, or its Change-Id with `--id`.
+/// The value is printed instead if the clipboard is not accessible, e.g. in a headless session.
+fn copy_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_copy(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let value = if matches.get_flag("id") {
+        let loading = util::loading();
+        let request_id = id.clone();
+        let change =
+            remote::run_request(gerrit, move |gerrit| gerrit.get_change(&request_id, None));
+        loading.stop();
+        change?.change_id
+    } else {
+        remote::change_url(&id)
+    };
+    let copied = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(&value));
+    match copied {
+        Ok(()) => cliprintln!(writer, "Copied {}", value).unwrap(),
+        Err(err) => cliprintln!(
+            writer,
+            "Could not access the clipboard ({}): {}",
+            err,
+            value
+        )
+        .unwrap(),
+    }
+    Ok(CmdAction::Ok)
+}
+
+/// Print out the git commands that fetch the current revision of a change and check it out,
+/// ready to paste in a repository of the project, or run them with `--run`.
+/// The revision is fetched from where the server tells for the scheme, http by default.
+fn checkout_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_checkout(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let scheme = matches.get_one::<String>("scheme").unwrap();
+    let loading = util::loading();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        gerrit.get_change(&id, Some(vec![AdditionalOpt::CurrentRevision]))
+    });
+    loading.stop();
+    let change = change?;
+    let (_, revision) = select_revision(&change, None)?;
+    let fetch = revision.fetch.as_ref();
+    let Some(fetch_info) = fetch.and_then(|fetch| fetch.get(scheme)) else {
+        let mut schemes: Vec<&String> = fetch.into_iter().flat_map(|fetch| fetch.keys()).collect();
+        schemes.sort();
+        return Err(CmdError::Failed(format!(
+            "no fetch info for scheme '{}', the server offers: {}",
+            scheme,
+            schemes.into_iter().cloned().collect::<Vec<_>>().join(", ")
+        )));
+    };
+    let commands = checkout_commands(&fetch_info.url, &fetch_info.r#ref);
+
+    if !matches.get_flag("run") {
+        let lines: Vec<String> = commands.iter().map(|command| command.join(" ")).collect();
+        cliprintln!(writer, "{}", lines.join(" && ")).unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    for command in commands {
+        cliprintln!(writer, "{}", theme::faded(command.join(" "))).unwrap();
+        let status = cli::run_program(std::process::Command::new(&command[0]).args(&command[1..]))
+            .map_err(|err| CmdError::Failed(format!("failed to run git: {}", err)))?;
+        if !status.success() {
+            return Err(CmdError::Failed(format!(
+                "'{}' exited with {}",
+                command.join(" "),
+                status
+            )));
+        }
+    }
+    Ok(CmdAction::Ok)
+}
+
+/// Get the git commands, as their args, that fetch a ref from a repository URL
+/// and check out what was fetched.
+fn checkout_commands(url: &str, reference: &str) -> [Vec<String>; 2] {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+    [
+        args(&["git", "fetch", url, reference]),
+        args(&["git", "checkout", "FETCH_HEAD"]),
+    ]
+}
+
+/// Show the change after the one shown last from the cached list, or before it if not `forward`.
+/// Without a change shown yet, `next` starts from the first change.
+fn show_adjacent_change(forward: bool, gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let index = {
+        let ctx_guard = CHANGE_CONTEXT.lock();
+        let ctx = ctx_guard.borrow();
+        adjacent_index(ctx.current, ctx.list.len(), forward)
+    };
+    let Some(index) = index else {
+        cliprintln!(cli::stdout(), "no more changes").unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    show_change(&[format!("${}", index + 1)], gerrit)
+}
+
+/// Position of the change next to `current` in a list of `len` changes, if any.
+fn adjacent_index(current: Option<usize>, len: usize, forward: bool) -> Option<usize> {
+    let index = match (current, forward) {
+        (None, true) => 0,
+        (None, false) => return None,
+        (Some(current), true) => current + 1,
+        (Some(current), false) => current.checked_sub(1)?,
     };
-    let loading_done = util::loading();
-    let changes_list: Vec<Vec<ChangeInfo>> = gerrit.query_changes(&query_param).unwrap();
-    loading_done.store(true, Ordering::SeqCst);
-    execute!(writer, MoveToColumn(0), Clear(ClearType::CurrentLine)).unwrap();
+    (index < len).then_some(index)
+}
+
+/// Mark the change with the given number as the one shown last, if it's in the cached list.
+fn set_current_change(number: &str) {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.current = ctx
+        .list
+        .iter()
+        .position(|change| change.number.to_string() == number)
+        .or(ctx.current);
+}
+
+/// Optional columns in the list of changes, printed before the subject.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ListColumns {
+    /// Name of the owner of the change
+    owner: bool,
+    /// Time since the change was last updated
+    age: bool,
+}
 
-    if changes_list.is_empty() {
+/// Print out a numbered list of changes, with a hint to the next page if there are more.
+/// Subjects are truncated to fit the terminal width, or wrapped into indented lines if `wrap`.
+fn print_changes(
+    writer: &mut impl Write,
+    changes: &[ChangeInfo],
+    more_changes: bool,
+    start: u32,
+    wrap: bool,
+    columns: ListColumns,
+) {
+    if changes.is_empty() {
         cliprintln!(writer, "no changes").unwrap();
     }
-    for (i, changes) in changes_list.iter().enumerate() {
-        for (j, change) in changes.iter().enumerate() {
+    // output that is not to a terminal is kept whole
+    let term_width = terminal::size()
+        .ok()
+        .filter(|_| cli::is_interactive())
+        .map(|(cols, _)| cols as usize);
+    // columns are as wide as their widest value, so they line up across rows
+    let statuses: Vec<_> = changes
+        .iter()
+        .map(|change| style_status(&change.status))
+        .collect();
+    let index_width = changes.len().to_string().len();
+    let number_width = changes
+        .iter()
+        .map(|change| change.number.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let status_width = statuses
+        .iter()
+        .map(|status| status.content().len())
+        .max()
+        .unwrap_or(0);
+    let owners: Vec<String> = changes
+        .iter()
+        .map(|change| account_name(&change.owner))
+        .collect();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let ages: Vec<String> = changes
+        .iter()
+        .map(|change| {
+            change
+                .updated
+                .as_ref()
+                .and_then(|updated| parse_timestamp(&updated.to_string()))
+                .map_or_else(String::new, |updated| {
+                    format_age(now.saturating_sub(updated))
+                })
+        })
+        .collect();
+    let owner_width = owners.iter().map(|owner| owner.width()).max().unwrap_or(0);
+    let age_width = ages.iter().map(|age| age.len()).max().unwrap_or(0);
+    let mut columns_width = index_width + 1 + number_width + 2 + status_width + 2;
+    if columns.owner {
+        columns_width += owner_width + 2;
+    }
+    if columns.age {
+        columns_width += age_width + 2;
+    }
+    for (i, (change, status)) in changes.iter().zip(statuses).enumerate() {
+        let index = format!("{:>width$}", i + 1, width = index_width);
+        let number = format!("{:width$}", change.number, width = number_width);
+        let status_padded = format!("{:width$}", status.content(), width = status_width);
+        queue!(
+            writer,
+            PrintStyled(theme::number(index)),
+            Print(" "),
+            PrintStyled(theme::change_number(number)),
+            Print("  "),
+            PrintStyled(StyledContent::new(*status.style(), status_padded)),
+            Print("  "),
+        )
+        .unwrap();
+        if columns.owner {
+            let padding = " ".repeat(owner_width - owners[i].width());
             queue!(
                 writer,
-                PrintStyledContent(format!("{:1}", i + j + 1).blue()),
-                Print(" "),
-                PrintStyledContent(change.number.to_string().dark_yellow()),
-                Print("  "),
-                PrintStyledContent(format!("{:3}", change.status).green()),
-                Print("  "),
-                Print(change.subject.to_string()),
-                SmartNewLine(1)
+                PrintStyled(theme::detail(&owners[i])),
+                Print(padding),
+                Print("  ")
             )
             .unwrap();
         }
+        if columns.age {
+            let age = format!("{:>width$}", ages[i], width = age_width);
+            queue!(writer, PrintStyled(theme::faded(age)), Print("  ")).unwrap();
+        }
+        // leave a few columns for the subject even in narrow terminals
+        let subject_width = term_width.map(|width| width.saturating_sub(columns_width).max(10));
+        match subject_width {
+            Some(width) if wrap => {
+                for (line_idx, line) in util::wrap_text(&change.subject, width).iter().enumerate() {
+                    if line_idx > 0 {
+                        queue!(writer, SmartNewLine(1), Print(" ".repeat(columns_width))).unwrap();
+                    }
+                    queue!(writer, Print(line)).unwrap();
+                }
+            }
+            Some(width) => {
+                queue!(writer, Print(util::truncate_text(&change.subject, width))).unwrap()
+            }
+            None => queue!(writer, Print(&change.subject)).unwrap(),
+        }
+        queue!(writer, SmartNewLine(1)).unwrap();
+    }
+
+    if more_changes {
+        let next_start = start + changes.len() as u32;
+        queue!(
+            writer,
+            PrintStyled(theme::faded(format!(
+                "... more results, use --start {}",
+                next_start
+            ))),
+            SmartNewLine(1)
+        )
+        .unwrap();
     }
     writer.flush().unwrap();
+}
 
-    let ctx_guard = CHANGE_CONTEXT.lock();
-    let mut ctx = ctx_guard.borrow_mut();
-    ctx.list = changes_list.into_iter().flatten().collect();
+/// Print out the saved queries with their names.
+fn list_saved_queries() -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let config = load_config()?;
+    if config.queries.is_empty() {
+        cliprintln!(writer, "no saved queries").unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    for (name, query) in &config.queries {
+        queue!(
+            writer,
+            PrintStyled(theme::name(name)),
+            Print("  "),
+            Print(query),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
 
+/// Save a query under a name in the configuration, replacing any query with the same name.
+fn save_query(name: &str, words: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    if words.is_empty() {
+        cliprintln!(writer, "Required query to save").unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    let mut config = load_config()?;
+    config.queries.insert(name.to_string(), words.join(" "));
+    save_config(&config)?;
+    cliprintln!(writer, "Saved query '{}'", name).unwrap();
     Ok(CmdAction::Ok)
 }
 
+/// Arguments of the `query` command.
+#[derive(Default)]
+struct QueryArgs {
+    /// Search terms, joined into a single query,
+    /// followed by the terms of the `--project`, `--branch` and `--status` options
+    words: Vec<String>,
+    limit: Option<u32>,
+    start: Option<u32>,
+    /// Name to save the query under
+    save: Option<String>,
+    /// Name of the saved query to run
+    run: Option<String>,
+    /// List the saved queries
+    list: bool,
+    /// Field to sort the changes by, instead of server order
+    sort: Option<SortField>,
+    /// Reverse the sort order
+    reverse: bool,
+    /// Wrap long subjects instead of truncating them
+    wrap: bool,
+    /// Optional columns to show in the list
+    columns: ListColumns,
+    /// Print out JSON instead of the list of changes
+    json: bool,
+}
+
+impl QueryArgs {
+    /// Parse the `query` command arguments.
+    /// Options are taken out by hand, the query is free text that may start with hyphens.
+    fn parse(args: &[String]) -> Result<Self, CmdError> {
+        let mut query_args = Self::default();
+        let mut filters = Vec::new();
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                "--limit" => {
+                    query_args.limit = Some(parse_count_option("--limit", args_iter.next())?)
+                }
+                "--start" => {
+                    query_args.start = Some(parse_count_option("--start", args_iter.next())?)
+                }
+                "--save" => query_args.save = Some(parse_name_option("--save", args_iter.next())?),
+                "--run" => query_args.run = Some(parse_name_option("--run", args_iter.next())?),
+                "--list" => query_args.list = true,
+                "--sort" => {
+                    let field = parse_name_option("--sort", args_iter.next())?;
+                    query_args.sort = Some(SortField::from_str(&field)?);
+                }
+                "--reverse" => query_args.reverse = true,
+                "--wrap" => query_args.wrap = true,
+                "--owner" => query_args.columns.owner = true,
+                "--age" => query_args.columns.age = true,
+                "--json" => query_args.json = true,
+                "--project" | "--branch" | "--status" => {
+                    let value = parse_name_option(arg, args_iter.next())?;
+                    let operator = arg.trim_start_matches('-');
+                    let statuses = ["open", "merged", "abandoned"];
+                    if operator == "status" && !statuses.contains(&value.as_str()) {
+                        return Err(CmdError::Failed(format!(
+                            "invalid value '{}' for --status, expected open, merged or abandoned",
+                            value
+                        )));
+                    }
+                    filters.push(query_term(operator, &value));
+                }
+                _ => query_args.words.push(arg.clone()),
+            }
+        }
+        // the options narrow down the query, like the terms they stand for
+        query_args.words.extend(filters);
+        Ok(query_args)
+    }
+}
+
+/// Format a query term of an operator, like `project:NAME`,
+/// quoting the value if it has spaces so it's taken as a whole.
+fn query_term(operator: &str, value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("{}:\"{}\"", operator, value)
+    } else {
+        format!("{}:{}", operator, value)
+    }
+}
+
+/// Parse the value of a count option, like `--limit N`.
+fn parse_count_option(option: &str, value: Option<&String>) -> Result<u32, CmdError> {
+    let value = value.ok_or_else(|| CmdError::Failed(format!("missing value for {}", option)))?;
+    u32::from_str(value)
+        .map_err(|_| CmdError::Failed(format!("invalid value '{}' for {}", value, option)))
+}
+
+/// Field of a change to sort query results by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortField {
+    Updated,
+    Number,
+    Status,
+    Subject,
+}
+
+impl FromStr for SortField {
+    type Err = CmdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "updated" => Ok(SortField::Updated),
+            "number" => Ok(SortField::Number),
+            "status" => Ok(SortField::Status),
+            "subject" => Ok(SortField::Subject),
+            _ => Err(CmdError::Failed(format!("invalid sort field '{}'", s))),
+        }
+    }
+}
+
+/// Sort changes by a field, optionally in reverse order.
+/// Changes without an update timestamp are always last when sorting by update.
+fn sort_changes(changes: &mut [ChangeInfo], field: SortField, reverse: bool) {
+    changes.sort_by(|a, b| {
+        let ordering = match field {
+            SortField::Updated => match (&a.updated, &b.updated) {
+                (Some(a), Some(b)) => a.to_string().cmp(&b.to_string()),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            SortField::Number => a.number.cmp(&b.number),
+            SortField::Status => a.status.to_string().cmp(&b.status.to_string()),
+            SortField::Subject => a.subject.cmp(&b.subject),
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Parse a timestamp as sent by Gerrit, like `2013-02-21 11:16:36.775000000` in UTC,
+/// into seconds since the Unix epoch.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim().split_once(' ')?;
+    let mut date_parts = date.splitn(3, '-').map(u64::from_str);
+    let (year, month, day) = (
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+    );
+    let time = time.split('.').next()?;
+    let mut time_parts = time.splitn(3, ':').map(u64::from_str);
+    let (hour, minute, second) = (
+        time_parts.next()?.ok()?,
+        time_parts.next()?.ok()?,
+        time_parts.next()?.ok()?,
+    );
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // days since the epoch of the civil date, counting years from March so leap days come last
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Format a number of seconds elapsed into a short relative time, like `3h ago`.
+fn format_age(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const MONTH: u64 = 30 * DAY;
+    const YEAR: u64 = 365 * DAY;
+    match seconds {
+        s if s < MINUTE => "just now".to_string(),
+        s if s < HOUR => format!("{}m ago", s / MINUTE),
+        s if s < DAY => format!("{}h ago", s / HOUR),
+        s if s < MONTH => format!("{}d ago", s / DAY),
+        s if s < YEAR => format!("{}mo ago", s / MONTH),
+        s => format!("{}y ago", s / YEAR),
+    }
+}
+
+/// Get the value of a name option, like `--run NAME`.
+fn parse_name_option(option: &str, value: Option<&String>) -> Result<String, CmdError> {
+    value
+        .cloned()
+        .ok_or_else(|| CmdError::Failed(format!("missing name for {}", option)))
+}
+
 /// Display change info
-pub fn show_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, ()> {
+pub fn show_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
     let mut writer = cli::stdout();
+    let matches = util::parse_args(command_show(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+
+    let json = matches.get_flag("json");
+    let patchset = matches.get_one::<u32>("patchset").copied();
+    let loading = util::loading_if(!json);
+    let request_id = id.clone();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        let mut additional_opts = vec![
+            AdditionalOpt::CurrentRevision,
+            AdditionalOpt::CurrentCommit,
+            AdditionalOpt::CurrentFiles,
+            AdditionalOpt::DetailedAccounts,
+            AdditionalOpt::DetailedLabels,
+        ];
+        // an older patchset is only found with all revisions
+        if patchset.is_some() {
+            additional_opts.extend([
+                AdditionalOpt::AllRevisions,
+                AdditionalOpt::AllCommits,
+                AdditionalOpt::AllFiles,
+            ]);
+        }
+        gerrit.get_change(request_id.as_str(), Some(additional_opts))
+    });
+    loading.stop();
+    let change = change?;
+    // the ID may be a Change-Id or commit SHA, the list is looked up by number
+    set_current_change(&change.number.to_string());
 
-    if args.len() != 1 {
-        cliprintln!(writer, "Required ID argument").unwrap();
+    if json {
+        util::print_json(&mut writer, &change)?;
         return Ok(CmdAction::Ok);
     }
 
-    let mut id = args.last().unwrap().clone();
-    let mut id_is_index = false;
-    if id.starts_with("$") {
-        id = id.split_off(1);
-        id_is_index = true;
+    print_change_line(&mut writer, &change);
+    queue!(writer, Print(&change.change_id), SmartNewLine(1)).unwrap();
+
+    print_revision(&mut writer, &change, patchset, matches.get_flag("files"))?;
+    print_labels(&mut writer, &change);
+
+    if matches.get_flag("comments") {
+        queue!(writer, SmartNewLine(1)).unwrap();
+        print_comments(&mut writer, id.as_str(), gerrit)?;
     }
-    let id_u32 = match u32::from_str(id.as_str()) {
-        Ok(id) => id,
-        Err(_) => {
-            cliprintln!(writer, "Argument is not a number").unwrap();
-            return Ok(CmdAction::Ok);
+
+    execute!(writer, SmartNewLine(1)).unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Print out the commit message of a revision of a change, and its files if `files`.
+/// The revision is the current one, or the one with the patchset number `patchset`.
+/// A missing current revision or commit message is reported, as the server may omit them,
+/// but a patchset that doesn't exist fails.
+fn print_revision(
+    writer: &mut impl Write,
+    change: &ChangeInfo,
+    patchset: Option<u32>,
+    files: bool,
+) -> Result<(), CmdError> {
+    let rev_info = match select_revision(change, patchset) {
+        Ok((_, rev_info)) => rev_info,
+        Err(_) if patchset.is_none() => {
+            cliprintln!(writer, "no current revision").unwrap();
+            return Ok(());
         }
+        Err(err) => return Err(err),
     };
+    if patchset.is_some() {
+        queue!(
+            writer,
+            Print("Patchset "),
+            PrintStyled(theme::number(rev_info.number)),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    let commit_msg = rev_info
+        .commit
+        .as_ref()
+        .and_then(|commit_info| commit_info.message.as_ref());
 
-    if id_is_index {
-        let ctx_guard = CHANGE_CONTEXT.lock();
-        let ctx = ctx_guard.borrow();
-        if id_u32 == 0 {
-            cliprintln!(writer, "ID out of bounds").unwrap();
-            return Ok(CmdAction::Ok);
-        }
-        if let Some(change) = ctx.list.get(id_u32 as usize - 1) {
-            id = change.number.to_string();
-        } else {
-            cliprintln!(writer, "ID out of bounds").unwrap();
-            return Ok(CmdAction::Ok);
+    queue!(writer, SmartNewLine(1)).unwrap();
+    match commit_msg {
+        Some(commit_msg) => {
+            let width = terminal::size().map_or(80, |(cols, _)| cols as usize);
+            for line in wrap_message_lines(commit_msg, width.saturating_sub(4)) {
+                queue!(writer, Print("    "), Print(line), SmartNewLine(1)).unwrap();
+            }
         }
+        None => queue!(writer, Print("    no commit message"), SmartNewLine(1)).unwrap(),
     }
 
-    let additional_opts = vec![
-        AdditionalOpt::CurrentRevision,
-        AdditionalOpt::CurrentCommit,
-        AdditionalOpt::CurrentFiles,
-        AdditionalOpt::DetailedAccounts,
-        AdditionalOpt::DetailedLabels,
-    ];
-    let loading_done = util::loading();
-    let change = gerrit
-        .get_change(id.as_str(), Some(additional_opts))
-        .unwrap();
-    loading_done.store(true, Ordering::SeqCst);
-    execute!(writer, MoveToColumn(0), Clear(ClearType::CurrentLine)).unwrap();
+    if files {
+        queue!(writer, SmartNewLine(1)).unwrap();
+        print_files(writer, rev_info);
+    }
+    Ok(())
+}
+
+/// Split a message into lines of at most `width` columns, wrapping the longer lines at whitespace.
+/// Lines that fit are kept as they are, with their indentation, and so are blank lines.
+fn wrap_message_lines(message: &str, width: usize) -> Vec<String> {
+    message
+        .lines()
+        .flat_map(|line| {
+            if line.width() <= width {
+                vec![line.to_string()]
+            } else {
+                util::wrap_text(line, width)
+            }
+        })
+        .collect()
+}
+
+/// Get the revision of a change with the patchset number `patchset`,
+/// or the current revision if not given, along with its revision id.
+/// Fails naming the patchsets available if the patchset is not found.
+fn select_revision(
+    change: &ChangeInfo,
+    patchset: Option<u32>,
+) -> Result<(&String, &RevisionInfo), CmdError> {
+    let revisions = change.revisions.as_ref();
+    let found = match patchset {
+        Some(patchset) => revisions
+            .into_iter()
+            .flatten()
+            .find(|(_, rev_info)| rev_info.number == patchset),
+        None => change
+            .current_revision
+            .as_ref()
+            .and_then(|rev_id| revisions?.get_key_value(rev_id)),
+    };
+    if let Some(revision) = found {
+        return Ok(revision);
+    }
+    let numbers = || {
+        revisions
+            .into_iter()
+            .flatten()
+            .map(|(_, rev_info)| rev_info.number)
+    };
+    let message = match (patchset, numbers().min(), numbers().max()) {
+        (Some(patchset), Some(min), Some(max)) => format!(
+            "change {} has no patchset {}, available are {} to {}",
+            change.number, patchset, min, max
+        ),
+        _ => format!("change {} has no such revision", change.number),
+    };
+    Err(CmdError::Failed(message))
+}
 
+/// Print out the files changed in a revision with their status and
+/// inserted/deleted lines, then a summary line, similar to `git show --stat`.
+fn print_files(writer: &mut impl Write, rev_info: &RevisionInfo) {
+    // magic files like /COMMIT_MSG are not part of the change
+    let files: BTreeMap<&String, &FileInfo> = rev_info
+        .files
+        .iter()
+        .flatten()
+        .filter(|(path, _)| !path.starts_with('/'))
+        .collect();
+    if files.is_empty() {
+        cliprintln!(writer, "no files").unwrap();
+        return;
+    }
+    let (mut insertions, mut deletions) = (0, 0);
+    for (path, file_info) in &files {
+        let status = file_info.status.unwrap_or('M');
+        let stat = if file_info.binary.unwrap_or(false) {
+            theme::faded("binary")
+        } else {
+            let inserted = file_info.lines_inserted.unwrap_or(0);
+            let deleted = file_info.lines_deleted.unwrap_or(0);
+            insertions += inserted;
+            deletions += deleted;
+            format!("+{}/-{}", inserted, deleted).stylize()
+        };
+        queue!(
+            writer,
+            Print(" "),
+            PrintStyled(theme::file_status(status)),
+            Print(" "),
+            Print(path),
+            Print("  "),
+            PrintStyled(stat),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
     queue!(
         writer,
-        PrintStyledContent(change.number.to_string().dark_yellow()),
-        Print("  "),
-        PrintStyledContent(format!("{:3}", change.status).green()),
-        Print("  "),
-        Print(change.subject.to_string()),
+        Print(format!(" {} files changed, ", files.len())),
+        PrintStyled(theme::positive(format!("{} insertions(+)", insertions))),
+        Print(", "),
+        PrintStyled(theme::negative(format!("{} deletions(-)", deletions))),
         SmartNewLine(1)
     )
     .unwrap();
+}
 
-    queue!(writer, Print(&change.change_id), SmartNewLine(1)).unwrap();
-
-    let curr_rev_id = change.current_revision.as_ref().unwrap();
-    let curr_rev_info = change.revisions.as_ref().unwrap().get(curr_rev_id).unwrap();
-    let curr_commit_info = curr_rev_info.commit.as_ref().unwrap();
-    let curr_commit_msg = curr_commit_info.message.as_ref().unwrap();
+/// Print out the files of the current revision of a change, numbered to be referenced
+/// by `$index` in `diff`.
+fn list_files(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_files(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let loading = util::loading();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        let additional_opts = vec![AdditionalOpt::CurrentRevision, AdditionalOpt::CurrentFiles];
+        gerrit.get_change(&id, Some(additional_opts))
+    });
+    loading.stop();
+    let change = change?;
+    let rev_info = change
+        .current_revision
+        .as_ref()
+        .and_then(|rev_id| change.revisions.as_ref()?.get(rev_id))
+        .ok_or_else(|| CmdError::Failed("no current revision".to_string()))?;
 
-    queue!(writer, SmartNewLine(1)).unwrap();
-    let lines = curr_commit_msg.lines();
-    for line in lines {
-        queue!(writer, Print("    "), Print(line), SmartNewLine(1)).unwrap();
+    // magic files like /COMMIT_MSG are not part of the change
+    let files: BTreeMap<&String, &FileInfo> = rev_info
+        .files
+        .iter()
+        .flatten()
+        .filter(|(path, _)| !path.starts_with('/'))
+        .collect();
+    let index_width = files.len().to_string().len();
+    for (i, (path, file_info)) in files.iter().enumerate() {
+        let status = file_info.status.unwrap_or('M');
+        queue!(
+            writer,
+            Print(format!("{:>width$} ", i + 1, width = index_width)),
+            PrintStyled(theme::file_status(status)),
+            Print(" "),
+            Print(path),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    if files.is_empty() {
+        queue!(writer, Print("no files"), SmartNewLine(1)).unwrap();
     }
+    writer.flush().unwrap();
 
-    execute!(writer, SmartNewLine(1)).unwrap();
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.files = files.into_keys().cloned().collect();
     Ok(CmdAction::Ok)
 }
+
+/// Print out the diff of a file in the current revision of a change, or the one
+/// of `--patchset`, against its parent.
+/// Added lines are green and deleted lines are red, unchanged lines are
+/// elided down to a few lines of context around the changes.
+/// With `--highlight`, the content is also colored by syntax, with added and deleted lines
+/// marked by their background instead, unless the language is unknown or the file is huge.
+fn diff_file(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_diff(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let Some(path) = resolve_file(matches.get_one::<String>("FILE").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    // a patchset is looked up among the revisions first, to tell which ones exist
+    let rev_id = match matches.get_one::<u32>("patchset") {
+        Some(patchset) => {
+            let loading = util::loading();
+            let request_id = id.clone();
+            let change = remote::run_request(gerrit, move |gerrit| {
+                gerrit.get_change(&request_id, Some(vec![AdditionalOpt::AllRevisions]))
+            });
+            loading.stop();
+            let change = change?;
+            select_revision(&change, Some(*patchset))?.0.clone()
+        }
+        None => "current".to_string(),
+    };
+    let loading = util::loading();
+    let request_path = path.clone();
+    let diff = remote::run_request(gerrit, move |gerrit| {
+        gerrit.get_diff(&id, &rev_id, &request_path)
+    });
+    loading.stop();
+    let diff = diff?;
+
+    queue!(writer, PrintStyled(theme::heading(path)), SmartNewLine(1)).unwrap();
+    if diff.binary.unwrap_or(false) {
+        queue!(writer, Print("binary file, no diff shown"), SmartNewLine(1)).unwrap();
+        writer.flush().unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    // the old and new content are highlighted apart, each one needs all of its lines in order
+    let (mut old_highlighter, mut new_highlighter) = if matches.get_flag("highlight") {
+        let line_count = diff
+            .content
+            .iter()
+            .flat_map(|chunk| [&chunk.ab, &chunk.a, &chunk.b])
+            .flatten()
+            .map(Vec::len)
+            .sum();
+        (
+            Highlighter::for_file(&path, line_count),
+            Highlighter::for_file(&path, line_count),
+        )
+    } else {
+        (None, None)
+    };
+    let chunks = diff.content.len();
+    for (i, chunk) in diff.content.iter().enumerate() {
+        if let Some(lines) = &chunk.ab {
+            let skipped = elided_context(lines.len(), i == 0, i + 1 == chunks).unwrap_or(0..0);
+            for (line_idx, line) in lines.iter().enumerate() {
+                if let Some(highlighter) = &mut old_highlighter {
+                    highlighter.highlight(line);
+                }
+                if line_idx == skipped.start && !skipped.is_empty() {
+                    let marker = format!("@@ {} unchanged lines @@", skipped.len());
+                    queue!(writer, PrintStyled(theme::detail(marker)), SmartNewLine(1)).unwrap();
+                }
+                if skipped.contains(&line_idx) {
+                    if let Some(highlighter) = &mut new_highlighter {
+                        highlighter.highlight(line);
+                    }
+                    continue;
+                }
+                print_diff_line(&mut writer, DiffLine::Unchanged, line, &mut new_highlighter);
+            }
+        }
+        for line in chunk.a.iter().flatten() {
+            print_diff_line(&mut writer, DiffLine::Deleted, line, &mut old_highlighter);
+        }
+        for line in chunk.b.iter().flatten() {
+            print_diff_line(&mut writer, DiffLine::Added, line, &mut new_highlighter);
+        }
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Kind of a line in a diff.
+#[derive(Clone, Copy)]
+enum DiffLine {
+    Unchanged,
+    Deleted,
+    Added,
+}
+
+/// Print out a line of a diff, prefixed by its kind: ` `, `-` or `+`.
+/// Without a highlighter, deleted lines are red and added lines are green.
+/// With a highlighter, the content is colored by syntax over a red or green background.
+fn print_diff_line(
+    writer: &mut impl Write,
+    kind: DiffLine,
+    line: &str,
+    highlighter: &mut Option<Highlighter>,
+) {
+    let (marker, color, background) = match kind {
+        DiffLine::Unchanged => (" ", Color::Reset, Color::Reset),
+        DiffLine::Deleted => ("-", Color::Red, Color::Rgb { r: 64, g: 0, b: 0 }),
+        DiffLine::Added => ("+", Color::Green, Color::Rgb { r: 0, g: 48, b: 0 }),
+    };
+    match highlighter {
+        Some(highlighter) => {
+            queue!(writer, PrintStyled(marker.with(color).on(background))).unwrap();
+            for piece in highlighter.highlight(line) {
+                queue!(writer, PrintStyled(piece.on(background))).unwrap();
+            }
+        }
+        None => {
+            let line = format!("{}{}", marker, line);
+            queue!(writer, PrintStyled(line.with(color))).unwrap();
+        }
+    }
+    queue!(writer, SmartNewLine(1)).unwrap();
+}
+
+/// Lines of context kept around changes in a diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Get the range of lines to elide from a chunk of `len` unchanged lines in a diff,
+/// keeping a few lines of context next to the changes before and after it.
+/// The first chunk has no change before it, neither has the last chunk after it.
+fn elided_context(len: usize, first: bool, last: bool) -> Option<Range<usize>> {
+    let start = if first { 0 } else { DIFF_CONTEXT_LINES };
+    let end = if last {
+        len
+    } else {
+        len.saturating_sub(DIFF_CONTEXT_LINES)
+    };
+    // a single elided line takes the same space as the marker replacing it
+    (end > start + 1).then_some(start..end)
+}
+
+/// Resolve the FILE argument of a command, which is either a path
+/// or an `$index` into the list of files from the last `files`.
+/// Prints out the reason and returns none if the index is not valid.
+fn resolve_file(arg: &str) -> Option<String> {
+    let Some(index) = arg.strip_prefix('$') else {
+        return Some(arg.to_string());
+    };
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    let file = usize::from_str(index)
+        .ok()
+        .and_then(|index| ctx.files.get(index.checked_sub(1)?));
+    if file.is_none() {
+        cliprintln!(cli::stdout(), "File index out of bounds").unwrap();
+    }
+    file.cloned()
+}
+
+/// Fetch and print out the comments of a change, grouped by file and sorted by line.
+fn print_comments(
+    writer: &mut impl Write,
+    id: &str,
+    gerrit: &mut GerritRestApi,
+) -> Result<(), CmdError> {
+    let loading = util::loading();
+    let request_id = id.to_string();
+    let comments = remote::run_request(gerrit, move |gerrit| {
+        gerrit.list_change_comments(&request_id)
+    });
+    loading.stop();
+    let comments: BTreeMap<String, Vec<CommentInfo>> = comments?.into_iter().collect();
+
+    if comments.values().all(Vec::is_empty) {
+        cliprintln!(writer, "no comments").unwrap();
+        return Ok(());
+    }
+    let width = terminal::size().map_or(80, |(cols, _)| cols as usize);
+    for (file, mut file_comments) in comments {
+        file_comments.sort_by_key(|comment| comment.line.unwrap_or(0));
+        queue!(writer, PrintStyled(theme::heading(file)), SmartNewLine(1)).unwrap();
+        for comment in file_comments {
+            let line = comment
+                .line
+                .map_or("file".to_string(), |line| format!("line {}", line));
+            let author = comment
+                .author
+                .as_ref()
+                .map_or("unknown".to_string(), account_name);
+            queue!(
+                writer,
+                Print("  "),
+                PrintStyled(theme::number(line)),
+                Print("  "),
+                PrintStyled(theme::name(author)),
+                Print("  "),
+                PrintStyled(theme::faded(&comment.updated)),
+                SmartNewLine(1)
+            )
+            .unwrap();
+            let message = comment.message.unwrap_or_default();
+            for line in message.lines() {
+                for wrapped in util::wrap_text(line, width.saturating_sub(4)) {
+                    queue!(writer, Print("    "), Print(wrapped), SmartNewLine(1)).unwrap();
+                }
+            }
+        }
+    }
+    writer.flush().unwrap();
+    Ok(())
+}
+
+/// Post a review with votes and a message on the current patchset of a change.
+pub fn review_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_review(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+
+    let mut labels = BTreeMap::new();
+    if let Some(vote) = matches.get_one::<i32>("code-review") {
+        labels.insert("Code-Review".to_string(), *vote);
+    }
+    if let Some(vote) = matches.get_one::<i32>("verified") {
+        labels.insert("Verified".to_string(), *vote);
+    }
+    let message = if matches.get_flag("edit") {
+        Some(compose_message(&mut writer)?)
+    } else if matches.get_flag("editor") {
+        Some(edit_text("")?).filter(|message| !message.trim().is_empty())
+    } else {
+        get_message(&matches)
+    };
+    if labels.is_empty() && message.is_none() {
+        cliprintln!(writer, "Nothing to review, give a vote or a message").unwrap();
+        return Ok(CmdAction::Ok);
+    }
+
+    // review explicitly the current revision, the one the user sees in `show`
+    let loading = util::loading();
+    let request_id = id.clone();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        gerrit.get_change(&request_id, Some(vec![AdditionalOpt::CurrentRevision]))
+    });
+    loading.stop();
+    let change = change?;
+    let Some(curr_rev_id) = change.current_revision.clone() else {
+        return Err(CmdError::Failed(format!(
+            "change {} has no current revision",
+            change.number
+        )));
+    };
+    let patchset = current_patchset(&change).unwrap_or_default();
+
+    let review_input = ReviewInput {
+        message,
+        labels: (!labels.is_empty()).then_some(labels),
+        ..Default::default()
+    };
+    let loading = util::loading();
+    let review = remote::run_update(gerrit, move |gerrit| {
+        gerrit.set_review(&id, &curr_rev_id, &review_input)
+    });
+    loading.stop();
+    let review = review?;
+
+    queue!(
+        writer,
+        Print("Reviewed patchset "),
+        PrintStyled(theme::number(patchset)),
+        Print(" of "),
+        PrintStyled(theme::change_number(change.number)),
+        SmartNewLine(1)
+    )
+    .unwrap();
+    for (label, vote) in review.labels.unwrap_or_default() {
+        let vote = theme::vote(format!("{:+}", vote));
+        queue!(
+            writer,
+            Print("    "),
+            Print(label),
+            Print(" "),
+            PrintStyled(vote),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Post a message on the current patchset of a change, without any votes.
+/// The message is given in words, or read from a file or composed in lines with `--edit`
+/// for longer messages.
+pub fn comment_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_comment(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let message = if let Some(path) = matches.get_one::<String>("file") {
+        std::fs::read_to_string(path)
+            .map_err(|err| CmdError::Failed(format!("failed to read {}: {}", path, err)))?
+    } else if matches.get_flag("edit") {
+        compose_message(&mut writer)?
+    } else if matches.get_flag("editor") {
+        edit_text("")?
+    } else {
+        matches
+            .get_many::<String>("MESSAGE")
+            .map(|words| words.cloned().collect::<Vec<String>>().join(" "))
+            .unwrap_or_default()
+    };
+    if message.trim().is_empty() {
+        cliprintln!(writer, "Nothing to post, give a message or a file").unwrap();
+        return Ok(CmdAction::Ok);
+    }
+
+    let review_input = ReviewInput {
+        message: Some(message),
+        ..Default::default()
+    };
+    let loading = util::loading();
+    let request_id = id.clone();
+    let review = remote::run_update(gerrit, move |gerrit| {
+        gerrit.set_review(&request_id, "current", &review_input)
+    });
+    loading.stop();
+    review?;
+
+    queue!(
+        writer,
+        Print("Commented on "),
+        PrintStyled(theme::change_number(id)),
+        SmartNewLine(1)
+    )
+    .unwrap();
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Print out the topic of a change, or set it if a topic is given, or remove it with `--delete`,
+/// or edit it in the editor of the user with `--editor`.
+/// After a change to the topic, the change line is printed out again with the new topic.
+pub fn topic_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_topic(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let mut topic = matches.get_one::<String>("TOPIC").cloned();
+    if topic.is_none() && !matches.get_flag("delete") {
+        let loading = util::loading();
+        let request_id = id.clone();
+        let current_topic =
+            remote::run_request(gerrit, move |gerrit| gerrit.get_topic(&request_id));
+        loading.stop();
+        let current_topic = current_topic?;
+        if !matches.get_flag("editor") {
+            if current_topic.is_empty() {
+                cliprintln!(writer, "no topic").unwrap();
+            } else {
+                cliprintln!(writer, "{}", current_topic).unwrap();
+            }
+            return Ok(CmdAction::Ok);
+        }
+        // topics are a single line, anything after the first one is ignored
+        let edited = edit_text(&current_topic)?;
+        let edited = edited.lines().next().unwrap_or_default().trim();
+        if edited == current_topic {
+            cliprintln!(writer, "Topic unchanged").unwrap();
+            return Ok(CmdAction::Ok);
+        }
+        topic = (!edited.is_empty()).then(|| edited.to_string());
+    }
+
+    let loading = util::loading();
+    let request_id = id.clone();
+    let result = match topic {
+        Some(topic) => {
+            let topic_input = TopicInput { topic: Some(topic) };
+            remote::run_update(gerrit, move |gerrit| {
+                gerrit.set_topic(&request_id, &topic_input).map(|_| ())
+            })
+        }
+        None => remote::run_update(gerrit, move |gerrit| gerrit.delete_topic(&request_id)),
+    };
+    let change = result
+        .and_then(|()| remote::run_request(gerrit, move |gerrit| gerrit.get_change(&id, None)));
+    loading.stop();
+    let change = change?;
+    print_change_header(&mut writer, &change);
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Abandon a change, with an optional message.
+pub fn abandon_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_abandon(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    if !confirmed(&matches, &format!("Abandon change {}?", id))? {
+        return Ok(CmdAction::Ok);
+    }
+    let abandon_input = AbandonInput {
+        message: get_message(&matches),
+        ..Default::default()
+    };
+    let loading = util::loading();
+    let change = remote::run_update(gerrit, move |gerrit| {
+        gerrit.abandon_change(&id, &abandon_input)
+    });
+    loading.stop();
+    let change = change?;
+    print_change_line(&mut writer, &change);
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Ask the user to confirm a destructive action, unless `--yes` was given.
+fn confirmed(matches: &ArgMatches, question: &str) -> Result<bool, CmdError> {
+    if matches.get_flag("yes") {
+        return Ok(true);
+    }
+    cli::confirm(question)
+        .map_err(|err| CmdError::Failed(format!("Failed to read the answer: {}", err)))
+}
+
+/// Restore an abandoned change, with an optional message.
+pub fn restore_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_restore(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let restore_input = RestoreInput {
+        message: get_message(&matches),
+    };
+    let loading = util::loading();
+    let change = remote::run_update(gerrit, move |gerrit| {
+        gerrit.restore_change(&id, &restore_input)
+    });
+    loading.stop();
+    let change = change?;
+    print_change_line(&mut writer, &change);
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Star a change, or remove its star if not `starred`, for the account logged in.
+/// The change line is printed out again, with the star if it's starred.
+pub fn star_change(
+    args: &[String],
+    starred: bool,
+    gerrit: &mut GerritRestApi,
+) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let cmd = if starred {
+        command_star()
+    } else {
+        command_unstar()
+    };
+    let matches = util::parse_args(cmd, args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let loading = util::loading();
+    let request_id = id.clone();
+    let result = remote::run_update(gerrit, move |gerrit| {
+        if starred {
+            gerrit.star_change("self", &request_id)
+        } else {
+            gerrit.unstar_change("self", &request_id)
+        }
+    });
+    let change = result
+        .and_then(|()| remote::run_request(gerrit, move |gerrit| gerrit.get_change(&id, None)));
+    loading.stop();
+    let change = change?;
+    print_change_line(&mut writer, &change);
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Submit a change, after checking it's submittable unless forced.
+pub fn submit_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_submit(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+
+    if !matches.get_flag("force") {
+        let loading = util::loading();
+        let request_id = id.clone();
+        let change = remote::run_request(gerrit, move |gerrit| {
+            let additional_opts = vec![AdditionalOpt::DetailedLabels, AdditionalOpt::Submittable];
+            gerrit.get_change(&request_id, Some(additional_opts))
+        });
+        loading.stop();
+        let change = change?;
+        if !change.submittable.unwrap_or(false) {
+            let blocking = blocking_labels(&change);
+            if blocking.is_empty() {
+                cliprintln!(writer, "Change {} is not submittable", change.number).unwrap();
+            } else {
+                cliprintln!(
+                    writer,
+                    "Change {} is not submittable, blocked by: {}",
+                    change.number,
+                    blocking.join(", ")
+                )
+                .unwrap();
+            }
+            return Ok(CmdAction::Ok);
+        }
+    }
+
+    if !confirmed(&matches, &format!("Submit change {}?", id))? {
+        return Ok(CmdAction::Ok);
+    }
+    let loading = util::loading();
+    let change = remote::run_update(gerrit, move |gerrit| {
+        gerrit.submit_change(&id, &SubmitInput::default())
+    });
+    loading.stop();
+    let change = change?;
+    print_change_line(&mut writer, &change);
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Rebase a change onto the tip of its target branch, or onto the given base.
+pub fn rebase_change(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let matches = util::parse_args(command_rebase(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let rebase_input = RebaseInput {
+        base: matches.get_one::<String>("base").cloned(),
+        ..Default::default()
+    };
+    let loading = util::loading();
+    let request_id = id.clone();
+    let change = remote::run_update(gerrit, move |gerrit| {
+        gerrit.rebase_change(&request_id, &rebase_input)
+    });
+    loading.stop();
+    if let Err(CmdError::Failed(message)) = &change {
+        let message = message.to_lowercase();
+        if message.contains("up to date") {
+            cliprintln!(writer, "Change {} is already up to date", id).unwrap();
+            return Ok(CmdAction::Ok);
+        }
+        if message.contains("conflict") {
+            return Err(CmdError::Failed(format!(
+                "change {} can't be rebased because of merge conflicts, rebase it locally",
+                id
+            )));
+        }
+    }
+    change?;
+
+    // the rebase creates a new patchset, fetch it to show the patchset advanced
+    let loading = util::loading();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        gerrit.get_change(&id, Some(vec![AdditionalOpt::CurrentRevision]))
+    });
+    loading.stop();
+    let change = change?;
+    print_change_line(&mut writer, &change);
+    if let Some(patchset) = current_patchset(&change) {
+        queue!(
+            writer,
+            Print("Rebased as patchset "),
+            PrintStyled(theme::number(patchset)),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Add a reviewer, or a CC, to a change and print out the updated reviewers.
+pub fn add_reviewer(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let matches = util::parse_args(command_add_reviewer(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let state = if matches.get_flag("cc") {
+        ReviewerState::Cc
+    } else {
+        ReviewerState::Reviewer
+    };
+    let reviewer_input = ReviewerInput {
+        reviewer: matches.get_one::<String>("ACCOUNT").unwrap().clone(),
+        state: Some(state),
+        ..Default::default()
+    };
+    let loading = util::loading();
+    let request_id = id.clone();
+    let result = remote::run_update(gerrit, move |gerrit| {
+        gerrit.add_reviewer(&request_id, &reviewer_input)
+    });
+    loading.stop();
+    let result = result?;
+    // the server reports accounts that can't be added in the result instead of failing
+    if let Some(error) = result.error {
+        return Err(CmdError::Failed(error));
+    }
+    print_reviewers(id.as_str(), gerrit)
+}
+
+/// Remove a reviewer, or a CC, from a change and print out the updated reviewers.
+pub fn remove_reviewer(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let matches = util::parse_args(command_remove_reviewer(), args)?;
+    let Some(id) = resolve_change_id(matches.get_one::<String>("ID").unwrap()) else {
+        return Ok(CmdAction::Ok);
+    };
+    let account = matches.get_one::<String>("ACCOUNT").unwrap().clone();
+    let loading = util::loading();
+    let request_id = id.clone();
+    let result = remote::run_update(gerrit, move |gerrit| {
+        gerrit.delete_reviewer(&request_id, &account)
+    });
+    loading.stop();
+    result?;
+    print_reviewers(id.as_str(), gerrit)
+}
+
+/// Fetch and print out the reviewers and CCs of a change.
+fn print_reviewers(id: &str, gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let loading = util::loading();
+    let request_id = id.to_string();
+    let change = remote::run_request(gerrit, move |gerrit| {
+        gerrit.get_change(&request_id, Some(vec![AdditionalOpt::DetailedAccounts]))
+    });
+    loading.stop();
+    let change = change?;
+    let reviewers = change.reviewers.unwrap_or_default();
+    for (title, state) in [
+        ("Reviewers", ReviewerState::Reviewer),
+        ("CC", ReviewerState::Cc),
+    ] {
+        let names: Vec<String> = reviewers
+            .get(&state)
+            .into_iter()
+            .flatten()
+            .map(account_name)
+            .collect();
+        queue!(
+            writer,
+            PrintStyled(theme::name(format!("{}:", title))),
+            Print(" "),
+            Print(names.join(", ")),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Get a readable name of an account: its full name, username, email or id, whichever is known.
+fn account_name(account: &AccountInfo) -> String {
+    account
+        .name
+        .clone()
+        .or_else(|| account.username.clone())
+        .or_else(|| account.email.clone())
+        .unwrap_or_else(|| account.account_id.to_string())
+}
+
+/// Print out the labels of a change, each with its overall vote followed by
+/// the votes of each reviewer. The overall vote is red when it blocks submission
+/// and green when the label is approved.
+fn print_labels(writer: &mut impl Write, change: &ChangeInfo) {
+    let Some(labels) = change.labels.as_ref().filter(|labels| !labels.is_empty()) else {
+        return;
+    };
+    let labels: BTreeMap<&String, _> = labels.iter().collect();
+    let name_width = labels.keys().map(|name| name.width()).max().unwrap_or(0);
+    queue!(
+        writer,
+        SmartNewLine(1),
+        PrintStyled(theme::heading("Labels")),
+        SmartNewLine(1)
+    )
+    .unwrap();
+    for (name, label) in labels {
+        let approvals: Vec<(String, i32)> = label
+            .all
+            .iter()
+            .flatten()
+            .filter_map(|approval| Some((account_name(&approval.account), approval.value?)))
+            .filter(|(_, vote)| *vote != 0)
+            .collect();
+        let votes: Vec<i32> = approvals.iter().map(|(_, vote)| *vote).collect();
+        let summary = format!("{:+}", label_summary(&votes));
+        let summary = if label.rejected.is_some() || label.blocking.unwrap_or(false) {
+            theme::negative(summary)
+        } else if label.approved.is_some() {
+            theme::positive(summary)
+        } else {
+            summary.stylize()
+        };
+        queue!(
+            writer,
+            Print(format!("  {:width$}  ", name, width = name_width)),
+            PrintStyled(summary)
+        )
+        .unwrap();
+        for (i, (reviewer, vote)) in approvals.into_iter().enumerate() {
+            let vote = theme::vote(format!("{:+}", vote));
+            let separator = if i == 0 { "  " } else { ", " };
+            queue!(
+                writer,
+                Print(separator),
+                Print(reviewer),
+                Print(" "),
+                PrintStyled(vote)
+            )
+            .unwrap();
+        }
+        queue!(writer, SmartNewLine(1)).unwrap();
+    }
+}
+
+/// Get the overall vote of a label from the votes of the reviewers:
+/// the lowest negative vote if any, as it blocks, otherwise the highest vote.
+fn label_summary(votes: &[i32]) -> i32 {
+    let lowest = votes.iter().copied().min().unwrap_or(0);
+    if lowest < 0 {
+        lowest
+    } else {
+        votes.iter().copied().max().unwrap_or(0)
+    }
+}
+
+/// Get the number of the current patchset of a change,
+/// if it was fetched with the current revision.
+fn current_patchset(change: &ChangeInfo) -> Option<u32> {
+    let curr_rev_id = change.current_revision.as_ref()?;
+    let curr_rev_info = change.revisions.as_ref()?.get(curr_rev_id)?;
+    Some(curr_rev_info.number)
+}
+
+/// Get the names of the labels that block a change from being submitted,
+/// which are the rejected labels and the required labels not approved yet.
+fn blocking_labels(change: &ChangeInfo) -> Vec<String> {
+    let Some(labels) = &change.labels else {
+        return Vec::new();
+    };
+    let mut blocking: Vec<String> = labels
+        .iter()
+        .filter(|(_, label)| {
+            label.blocking.unwrap_or(false)
+                || label.rejected.is_some()
+                || (label.approved.is_none() && !label.optional.unwrap_or(false))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    blocking.sort();
+    blocking
+}
+
+/// Print out the one line summary of a change: number, status and subject,
+/// with a star before the subject if the account logged in starred the change.
+fn print_change_line(writer: &mut impl Write, change: &ChangeInfo) {
+    queue!(
+        writer,
+        PrintStyled(theme::change_number(change.number)),
+        Print("  "),
+        PrintStyled(style_status(&change.status)),
+        Print("  "),
+    )
+    .unwrap();
+    if change.starred == Some(true) {
+        queue!(writer, PrintStyled(theme::star("★")), Print(" ")).unwrap();
+    }
+    queue!(writer, Print(change.subject.to_string()), SmartNewLine(1)).unwrap();
+}
+
+/// Print out the one line summary of a change followed by its topic, if any.
+fn print_change_header(writer: &mut impl Write, change: &ChangeInfo) {
+    print_change_line(writer, change);
+    if let Some(topic) = change.topic.as_ref().filter(|topic| !topic.is_empty()) {
+        queue!(
+            writer,
+            Print("topic: "),
+            PrintStyled(theme::detail(topic)),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+}
+
+/// Set the colors of statuses replacing the default ones, from configuration.
+pub fn set_status_colors(colors: HashMap<String, Color>) {
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.status_colors = colors;
+}
+
+/// Style a change status with a color specific to the status.
+pub fn style_status(status: &ChangeStatus) -> StyledContent<String> {
+    style_status_name(&status.to_string())
+}
+
+/// Style a change status by its name with the style of the theme, by default
+/// NEW in yellow, MERGED in green, ABANDONED in red and DRAFT dimmed,
+/// unless the color is replaced in configuration.
+fn style_status_name(name: &str) -> StyledContent<String> {
+    let content = format!("{:3}", name);
+    let ctx_guard = CHANGE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    if let Some(color) = ctx.status_colors.get(name) {
+        return content.with(*color);
+    }
+    theme::status(name, content)
+}
+
+/// Resolve the ID argument of a change, which is either an `$index` into the list of changes
+/// from the last query, a change number, a Change-Id or a commit SHA.
+/// Change-Ids and SHAs are passed on as given, for the server to resolve.
+/// Prints out the reason and returns none if it's not valid.
+fn resolve_change_id(arg: &str) -> Option<String> {
+    let mut writer = cli::stdout();
+
+    if let Some(index) = arg.strip_prefix('$') {
+        let Ok(index) = usize::from_str(index) else {
+            cliprintln!(writer, "Index is not a number").unwrap();
+            return None;
+        };
+        let ctx_guard = CHANGE_CONTEXT.lock();
+        let ctx = ctx_guard.borrow();
+        let change = index.checked_sub(1).and_then(|i| ctx.list.get(i));
+        let Some(change) = change else {
+            cliprintln!(writer, "ID out of bounds").unwrap();
+            return None;
+        };
+        return Some(change.number.to_string());
+    }
+    if u32::from_str(arg).is_ok() || is_change_id(arg) || is_commit_sha(arg) {
+        return Some(arg.to_string());
+    }
+    cliprintln!(
+        writer,
+        "Argument is not a change number, Change-Id or commit SHA"
+    )
+    .unwrap();
+    None
+}
+
+/// Whether a string is a Change-Id, an `I` followed by 40 hexadecimal digits.
+fn is_change_id(id: &str) -> bool {
+    id.strip_prefix('I')
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Whether a string is a commit SHA, possibly abbreviated down to 7 hexadecimal digits.
+fn is_commit_sha(id: &str) -> bool {
+    (7..=40).contains(&id.len()) && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Get the message argument of a command, joining its words with spaces.
+fn get_message(matches: &ArgMatches) -> Option<String> {
+    matches
+        .get_many::<String>("message")
+        .map(|words| words.cloned().collect::<Vec<String>>().join(" "))
+}
+
+/// Read a message of multiple lines typed by the user, see [`cli::read_text`].
+/// Discarding the message fails the command, so nothing is posted without it.
+fn compose_message(writer: &mut impl Write) -> Result<String, CmdError> {
+    cliprintln!(
+        writer,
+        "{}",
+        theme::faded("Type the message, end it with a lone '.' or CTRL+D, discard it with CTRL+C")
+    )
+    .unwrap();
+    match cli::read_text() {
+        Ok(Some(message)) => Ok(message),
+        Ok(None) => Err(CmdError::Failed("message discarded".to_string())),
+        Err(err) => Err(CmdError::Failed(format!(
+            "failed to read the message: {}",
+            err
+        ))),
+    }
+}
+
+/// Edit a text in the editor of the user, see [`cli::edit_in_editor`].
+fn edit_text(initial: &str) -> Result<String, CmdError> {
+    cli::edit_in_editor(initial)
+        .map_err(|err| CmdError::Failed(format!("failed to edit in the editor: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crossterm::style::{Color, Stylize};
+
+    use gerlib::changes::{ChangeInfo, RevisionInfo};
+
+    use crate::change::{
+        adjacent_index, cache_changes, checkout_commands, elided_context, flatten_batches,
+        format_age, label_summary, parse_timestamp, print_change_line, print_changes,
+        print_revision, resolve_change_id, select_revision, set_status_colors, style_status_name,
+        wrap_message_lines, ListColumns, QueryArgs, SortField,
+    };
+    use crate::cli;
+    use crate::util::CmdError;
+
+    fn to_args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_query_args_free_form() {
+        let query_args = QueryArgs::parse(&to_args("project:x branch:main is:open")).unwrap();
+        assert_eq!(query_args.words.join(" "), "project:x branch:main is:open");
+    }
+
+    #[test]
+    fn test_query_args_options() {
+        let query_args = QueryArgs::parse(&to_args("-is:wip --limit 10 owner:self")).unwrap();
+        assert_eq!(query_args.words, vec!["-is:wip", "owner:self"]);
+        assert_eq!(query_args.limit, Some(10));
+        assert_eq!(query_args.start, None);
+        assert!(QueryArgs::parse(&to_args("--start x")).is_err());
+    }
+
+    #[test]
+    fn test_query_args_sort() {
+        let query_args = QueryArgs::parse(&to_args("is:open --sort updated --reverse")).unwrap();
+        assert_eq!(query_args.sort, Some(SortField::Updated));
+        assert!(query_args.reverse);
+        assert!(QueryArgs::parse(&to_args("--sort size")).is_err());
+    }
+
+    #[test]
+    fn test_query_args_saved() {
+        let query_args = QueryArgs::parse(&to_args("--save mine owner:self is:open")).unwrap();
+        assert_eq!(query_args.save.as_deref(), Some("mine"));
+        assert_eq!(query_args.words, vec!["owner:self", "is:open"]);
+        let query_args = QueryArgs::parse(&to_args("--run mine")).unwrap();
+        assert_eq!(query_args.run.as_deref(), Some("mine"));
+        assert!(query_args.words.is_empty());
+        assert!(QueryArgs::parse(&to_args("--run")).is_err());
+    }
+
+    #[test]
+    fn test_query_args_filters() {
+        let query_args = QueryArgs::parse(&to_args(
+            "--project gerrit owner:self --branch main --status merged -is:wip",
+        ))
+        .unwrap();
+        assert_eq!(
+            query_args.words.join(" "),
+            "owner:self -is:wip project:gerrit branch:main status:merged"
+        );
+        let mut args = to_args("--project");
+        args.push("my project".to_string());
+        let query_args = QueryArgs::parse(&args).unwrap();
+        assert_eq!(query_args.words, vec!["project:\"my project\""]);
+        assert!(QueryArgs::parse(&to_args("--status draft")).is_err());
+        assert!(QueryArgs::parse(&to_args("--branch")).is_err());
+    }
+
+    #[test]
+    fn test_style_status_colors() {
+        /// Restores the default colors even if an assertion fails.
+        struct ResetStatusColors;
+        impl Drop for ResetStatusColors {
+            fn drop(&mut self) {
+                set_status_colors(HashMap::new());
+            }
+        }
+
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        assert_eq!(style_status_name("MERGED"), "MERGED".to_string().green());
+        assert_eq!(style_status_name("DRAFT"), "DRAFT".to_string().dim());
+        let reset = ResetStatusColors;
+        set_status_colors(HashMap::from([("NEW".to_string(), Color::Blue)]));
+        assert_eq!(style_status_name("NEW"), "NEW".to_string().blue());
+        assert_eq!(
+            style_status_name("ABANDONED"),
+            "ABANDONED".to_string().red()
+        );
+        drop(reset);
+        assert_eq!(style_status_name("NEW"), "NEW".to_string().yellow());
+    }
+
+    #[test]
+    fn test_no_color_output() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let changes: Vec<ChangeInfo> = [7, 12345]
+            .into_iter()
+            .map(|number| ChangeInfo {
+                number,
+                subject: "Fix".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut output = Vec::new();
+        let _no_color = cli::no_color();
+        print_changes(
+            &mut output,
+            &changes,
+            false,
+            0,
+            false,
+            ListColumns::default(),
+        );
+        print_change_line(&mut output, &changes[0]);
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains('\x1b'));
+        // columns are padded so the subjects line up
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0].find("Fix"), lines[1].find("Fix"));
+        assert!(lines[0].starts_with("1 7    "));
+    }
+
+    #[test]
+    fn test_checkout_commands() {
+        let [fetch, checkout] = checkout_commands(
+            "https://review.example.com/project",
+            "refs/changes/45/12345/3",
+        );
+        assert_eq!(
+            fetch,
+            [
+                "git",
+                "fetch",
+                "https://review.example.com/project",
+                "refs/changes/45/12345/3"
+            ]
+        );
+        assert_eq!(checkout, ["git", "checkout", "FETCH_HEAD"]);
+    }
+
+    #[test]
+    fn test_starred_change_line() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let mut change = ChangeInfo {
+            number: 7,
+            subject: "Fix".to_string(),
+            ..Default::default()
+        };
+        let line = |change: &ChangeInfo| {
+            let mut output = Vec::new();
+            let _no_color = cli::no_color();
+            print_change_line(&mut output, change);
+            String::from_utf8(output).unwrap()
+        };
+        assert!(!line(&change).contains('★'));
+        change.starred = Some(true);
+        assert!(line(&change).contains("★ Fix"));
+    }
+
+    #[test]
+    fn test_owner_and_age_columns() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let changes: Vec<ChangeInfo> = ["Ann", "Bartholomew"]
+            .into_iter()
+            .map(|name| {
+                let mut change = ChangeInfo {
+                    number: 42,
+                    subject: "Fix".to_string(),
+                    ..Default::default()
+                };
+                change.owner.name = Some(name.to_string());
+                change
+            })
+            .collect();
+        let columns = ListColumns {
+            owner: true,
+            age: false,
+        };
+        let mut output = Vec::new();
+        let _no_color = cli::no_color();
+        print_changes(&mut output, &changes, false, 0, false, columns);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].contains("Ann"));
+        assert!(lines[1].contains("Bartholomew"));
+        assert_eq!(lines[0].find("Fix"), lines[1].find("Fix"));
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01 00:00:00.000000000"), Some(0));
+        assert_eq!(
+            parse_timestamp("2013-02-21 11:16:36.775000000"),
+            Some(1361445396)
+        );
+        assert_eq!(parse_timestamp("2024-02-29 00:00:00"), Some(1709164800));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(30), "just now");
+        assert_eq!(format_age(5 * 60), "5m ago");
+        assert_eq!(format_age(3 * 3600 + 59), "3h ago");
+        assert_eq!(format_age(2 * 86400), "2d ago");
+        assert_eq!(format_age(400 * 86400), "1y ago");
+    }
+
+    #[test]
+    fn test_index_runs_across_batches() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let change = |number| ChangeInfo {
+            number,
+            ..Default::default()
+        };
+        let batches = vec![vec![change(101), change(102)], vec![change(201)]];
+        cache_changes(flatten_batches(batches));
+        assert_eq!(resolve_change_id("$1").as_deref(), Some("101"));
+        assert_eq!(resolve_change_id("$2").as_deref(), Some("102"));
+        assert_eq!(resolve_change_id("$3").as_deref(), Some("201"));
+        assert_eq!(resolve_change_id("$4"), None);
+        assert_eq!(resolve_change_id("$0"), None);
+        cache_changes(Vec::new());
+    }
+
+    #[test]
+    fn test_resolve_change_id_forms() {
+        let change_id = "I8473b95934b5732ac55d26311a706c9c2bde9940";
+        assert_eq!(resolve_change_id("12345").as_deref(), Some("12345"));
+        assert_eq!(resolve_change_id(change_id).as_deref(), Some(change_id));
+        assert_eq!(resolve_change_id("a1b2c3d").as_deref(), Some("a1b2c3d"));
+        assert_eq!(resolve_change_id("I8473b959"), None);
+        assert_eq!(resolve_change_id("a1b2"), None);
+        assert_eq!(resolve_change_id("not-a-change"), None);
+    }
+
+    #[test]
+    fn test_adjacent_index() {
+        assert_eq!(adjacent_index(None, 3, true), Some(0));
+        assert_eq!(adjacent_index(None, 3, false), None);
+        assert_eq!(adjacent_index(Some(0), 3, true), Some(1));
+        assert_eq!(adjacent_index(Some(2), 3, true), None);
+        assert_eq!(adjacent_index(Some(2), 3, false), Some(1));
+        assert_eq!(adjacent_index(Some(0), 3, false), None);
+        assert_eq!(adjacent_index(None, 0, true), None);
+    }
+
+    #[test]
+    fn test_select_revision() {
+        let revision = |number| RevisionInfo {
+            number,
+            ..Default::default()
+        };
+        let change = ChangeInfo {
+            number: 7,
+            current_revision: Some("c2".to_string()),
+            revisions: Some(
+                [
+                    ("c1".to_string(), revision(1)),
+                    ("c2".to_string(), revision(2)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(select_revision(&change, None).unwrap().0, "c2");
+        assert_eq!(select_revision(&change, Some(1)).unwrap().0, "c1");
+        match select_revision(&change, Some(5)) {
+            Err(CmdError::Failed(message)) => {
+                assert_eq!(message, "change 7 has no patchset 5, available are 1 to 2")
+            }
+            _ => panic!("patchset 5 should not be found"),
+        }
+    }
+
+    #[test]
+    fn test_show_without_revision() {
+        let mut change = ChangeInfo {
+            number: 7,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        print_revision(&mut output, &change, None, true).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("no current revision"));
+
+        // a revision without commit info, like when the server omits it
+        change.current_revision = Some("c1".to_string());
+        change.revisions = Some(
+            [("c1".to_string(), RevisionInfo::default())]
+                .into_iter()
+                .collect(),
+        );
+        let mut output = Vec::new();
+        print_revision(&mut output, &change, None, false).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("no commit message"));
+    }
+
+    #[test]
+    fn test_wrap_message_lines() {
+        let message = "Fix the parser\n\nThe parser failed on long input\n  - keep indent";
+        assert_eq!(
+            wrap_message_lines(message, 16),
+            vec![
+                "Fix the parser",
+                "",
+                "The parser",
+                "failed on long",
+                "input",
+                "  - keep indent"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_summary() {
+        assert_eq!(label_summary(&[]), 0);
+        assert_eq!(label_summary(&[1, 2]), 2);
+        assert_eq!(label_summary(&[2, -1, 1]), -1);
+        assert_eq!(label_summary(&[-1, -2]), -2);
+    }
+
+    #[test]
+    fn test_elided_context() {
+        // between two changes, context is kept on both sides
+        assert_eq!(elided_context(10, false, false), Some(3..7));
+        assert_eq!(elided_context(7, false, false), None);
+        // before the first change and after the last one, only one side is kept
+        assert_eq!(elided_context(10, true, false), Some(0..7));
+        assert_eq!(elided_context(10, false, true), Some(3..10));
+        assert_eq!(elided_context(2, true, true), Some(0..2));
+        assert_eq!(elided_context(1, true, true), None);
+    }
+}
@@ -7,7 +7,7 @@
 //! # Example:
 //! ```
 //! fn main() -> io::Result<()> {
-//!     cli::initialize();
+//!     let _cli_guard = cli::initialize()?;
 //!     cli::set_prefix("myprogram".to_string().stylize());
 //!     cli::set_symbol(">".to_string().green());
 //!     let mut stdout = cli::stdout();
@@ -37,23 +37,41 @@
 
 use std::cell::RefCell;
 use std::fmt;
+use std::fmt::Display;
 use std::io::{Stdout, Write};
 use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
 use std::time::Duration;
 
 use crossterm::cursor::{
-    MoveDown, MoveLeft, MoveToColumn, MoveToNextLine, MoveToPreviousLine, MoveUp,
+    MoveDown, MoveLeft, MoveTo, MoveToColumn, MoveToNextLine, MoveToPreviousLine, MoveUp,
+    RestorePosition, SavePosition,
 };
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use crossterm::style::{Print, PrintStyledContent, StyledContent, Stylize};
 use crossterm::terminal::{Clear, ClearType, ScrollUp};
+use crossterm::tty::IsTty;
 use crossterm::{cursor, event, execute, queue, style, terminal};
 use once_cell::sync::Lazy;
 use parking_lot::ReentrantMutex;
+use unicode_width::UnicodeWidthStr;
 
+use crate::history;
 use crate::history::HistoryHandle;
+use crate::keymap::{Action, Keymap};
+use crate::theme;
 use crate::util;
-use crate::util::TrieUtils;
+use crate::util::{CompletionTries, InputWord, TrieUtils};
+
+/// Whether the terminal is in raw mode and must be restored on exit.
+/// It's kept out of `CLI` so the terminal can be restored from the panic hook,
+/// even if the panic happened while `CLI` was borrowed.
+static RAW_MODE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether output is styled with colors and attributes, see [`PrintStyled`].
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
 
 /// Global variable holding CLI data.
 /// It is lazy-initialized on first access.
@@ -66,16 +84,78 @@ static CLI: Lazy<ReentrantMutex<RefCell<CliSingleton>>> =
 /// Nothing fancy, just data that should only have once instance
 /// as the CLI is only one per process instance.
 struct CliSingleton {
+    /// Name of the remote in use, shown in place of the prefix when set.
+    pub remote: Option<StyledContent<String>>,
     pub prefix: StyledContent<String>,
+    /// Names of the modes entered, shown after the prefix.
+    pub modes: Vec<StyledContent<String>>,
     pub symbol: StyledContent<String>,
+    /// Whether the terminal is in raw mode for the interactive shell,
+    /// otherwise output is plain text, suitable for pipes.
+    pub interactive: bool,
+    /// Terminal row where the CLI started, just below the program invocation line.
+    /// It goes up as the terminal scrolls, until it's scrolled out of the screen and is `None`.
+    pub start_row: Option<u16>,
+    /// Fall back to fuzzy matching when no completion candidate starts with the input.
+    pub fuzzy_completion: bool,
+    /// Ring the terminal bell on invalid input and on completion without candidates.
+    pub bell: bool,
+    /// Ask for confirmation before CTRL+D quits the program from the root mode.
+    pub confirm_quit: bool,
+    /// Bindings of keys to the editing actions of the prompt.
+    pub keymap: Keymap,
+}
+
+impl CliSingleton {
+    /// Pieces of the prompt before the symbol, styled on their own:
+    /// the remote name or else the prefix, followed by the modes separated by `/`.
+    fn prefix_pieces(&self) -> Vec<StyledContent<String>> {
+        let mut pieces = vec![self.remote.clone().unwrap_or_else(|| self.prefix.clone())];
+        for mode in &self.modes {
+            pieces.push("/".to_string().stylize());
+            pieces.push(mode.clone());
+        }
+        pieces
+    }
+}
+
+/// Serializes the tests that change global state, like the prompt or the cached changes.
+#[cfg(test)]
+pub static STATE_TEST_LOCK: parking_lot::Mutex<()> = parking_lot::const_mutex(());
+
+/// Color turned off for as long as this is alive, see [`no_color`].
+#[cfg(test)]
+pub struct NoColorGuard;
+
+#[cfg(test)]
+impl Drop for NoColorGuard {
+    fn drop(&mut self) {
+        set_color(true);
+    }
+}
+
+/// Turn color off so tests can compare plain output, until the guard returned is dropped.
+/// Color is turned back on even if an assertion fails meanwhile.
+#[cfg(test)]
+pub fn no_color() -> NoColorGuard {
+    set_color(false);
+    NoColorGuard
 }
 
 /// Default initialization of `CliSingleton`
 impl Default for CliSingleton {
     fn default() -> Self {
         CliSingleton {
+            remote: None,
             prefix: "cli".to_string().stylize(),
+            modes: Vec::new(),
             symbol: ">".to_string().stylize(),
+            interactive: false,
+            start_row: None,
+            fuzzy_completion: false,
+            bell: false,
+            confirm_quit: false,
+            keymap: Keymap::default(),
         }
     }
 }
@@ -87,29 +167,119 @@ pub struct CliGuard;
 
 /// Initialize the terminal for this CLI shell.
 /// This command will configure the terminal to be locked to our shell
-/// thus every input is handled from our application only from this point on
-pub fn initialize() -> CliGuard {
+/// thus every input is handled from our application only from this point on.
+/// It fails if stdin or stdout is not a terminal, like when output is redirected to a file,
+/// since the shell reads keys from one and moves the cursor on the other.
+/// The terminal is left as it was when it fails.
+pub fn initialize() -> std::io::Result<CliGuard> {
+    for (name, is_tty) in [
+        ("stdin", std::io::stdin().is_tty()),
+        ("stdout", std::io::stdout().is_tty()),
+    ] {
+        if !is_tty {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} is not a terminal", name),
+            ));
+        }
+    }
+    let start_row = cursor::position()?.1;
+    install_panic_hook();
+    terminal::enable_raw_mode()?;
+    RAW_MODE_ENABLED.store(true, Ordering::SeqCst);
+    // from here on the terminal is restored when the guard drops, even on failure
+    let guard = CliGuard;
+    {
+        let cli_guard = CLI.lock();
+        let mut cli = cli_guard.borrow_mut();
+        *cli = CliSingleton::default();
+        cli.interactive = true;
+        cli.start_row = Some(start_row);
+    }
+    let mut stdout = stdout();
+    execute!(stdout, cursor::Show, style::ResetColor)?;
+    Ok(guard)
+}
+
+/// Initialize the CLI for non-interactive use, like when input is piped from stdin.
+/// The terminal is left untouched, raw mode is never enabled,
+/// and output is printed as plain lines without cursor manipulation.
+pub fn initialize_plain() -> CliGuard {
     let cli_guard = CLI.lock();
     let mut cli = cli_guard.borrow_mut();
     *cli = CliSingleton::default();
-    terminal::enable_raw_mode().unwrap();
-    let mut stdout = stdout();
-    execute!(stdout, cursor::Show, style::ResetColor).unwrap();
     CliGuard
 }
 
+/// Check whether the CLI was initialized as an interactive shell.
+pub fn is_interactive() -> bool {
+    let cli_guard = CLI.lock();
+    let cli = cli_guard.borrow();
+    cli.interactive
+}
+
+/// Account for `rows` the terminal content was scrolled up,
+/// so the CLI start row moves up together with the content.
+fn scrolled_up(rows: u16) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.start_row = cli.start_row.and_then(|row| row.checked_sub(rows));
+}
+
+/// Clear all lines printed since the program was invoked.
+/// The terminal is cleared from the CLI start row down to the bottom of the screen,
+/// and the cursor is placed at the start row.
+/// If the start row was already scrolled out of the screen,
+/// the whole screen is cleared and the top of the screen becomes the new start row.
+pub fn clear() {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    if !cli.interactive {
+        return;
+    }
+    let start_row = cli.start_row.unwrap_or(0);
+    cli.start_row = Some(start_row);
+    let mut writer = stdout();
+    execute!(
+        writer,
+        MoveTo(0, start_row),
+        Clear(ClearType::FromCursorDown)
+    )
+    .unwrap();
+}
+
 /// Return the terminal to its normal state.
 /// The terminal is unlocked from our application.
 /// Input is handled by the terminal from now on and the attributes are reset.
 /// The CLI shell is finished and the terminal is free.
+/// This is a no-op if the terminal was already restored, e.g. by the panic hook.
 fn deinitialize() {
-    terminal::disable_raw_mode().unwrap();
+    if !RAW_MODE_ENABLED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    // errors are ignored as this may run while panicking
+    _ = terminal::disable_raw_mode();
     let mut stdout = std::io::stdout();
-    execute!(stdout, cursor::Show, style::ResetColor).unwrap();
+    _ = execute!(stdout, cursor::Show, style::ResetColor);
     // let terminal commands flush for certain
     std::thread::sleep(Duration::from_millis(50));
 }
 
+/// Install a panic hook that restores the terminal before the panic message is printed,
+/// otherwise the message would be mangled by raw mode and the terminal left unusable.
+/// The previous hook is called afterwards to print the message.
+/// The hook is installed only once, no matter how many times this is called.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let prev_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            deinitialize();
+            prev_hook(info);
+        }));
+    });
+}
+
 /// Deinitialize the CLI when guard drops.
 impl Drop for CliGuard {
     fn drop(&mut self) {
@@ -167,6 +337,45 @@ pub fn set_prefix(p: StyledContent<String>) {
     cli.prefix = p;
 }
 
+/// Get the prompt's full prefix as plain text, composed of the remote or prefix and modes.
+pub fn get_prefix() -> String {
+    let cli_guard = CLI.lock();
+    let cli = cli_guard.borrow();
+    cli.prefix_pieces()
+        .iter()
+        .map(|piece| piece.content().as_str())
+        .collect()
+}
+
+/// Update the prompt's remote name, or remove it with `None`.
+/// The remote name is printed in place of the prefix, and it's kept when modes change.
+/// Prompt will look like this:
+/// remote/mode>
+pub fn set_remote(r: Option<StyledContent<String>>) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.remote = r;
+}
+
+/// Update the prompt's path of modes entered, from the outermost mode.
+/// Prompt will look like this:
+/// prefix/mode/submode>
+pub fn set_modes(m: Vec<StyledContent<String>>) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.modes = m;
+}
+
+/// Get the names of the modes entered, from the outermost mode.
+pub fn get_modes() -> Vec<String> {
+    let cli_guard = CLI.lock();
+    let cli = cli_guard.borrow();
+    cli.modes
+        .iter()
+        .map(|mode| mode.content().clone())
+        .collect()
+}
+
 /// Update the prompt's symbol string.
 /// Prompt will look like this:
 /// prefix>
@@ -177,22 +386,105 @@ pub fn set_symbol(s: StyledContent<String>) {
     cli.symbol = s;
 }
 
+/// Enable fuzzy matching of completion candidates, used when none matches by prefix.
+pub fn set_fuzzy_completion(enabled: bool) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.fuzzy_completion = enabled;
+}
+
+/// Enable the terminal bell on invalid input and on completion without candidates.
+pub fn set_bell(enabled: bool) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.bell = enabled;
+}
+
+/// Ask for confirmation before CTRL+D quits the program from the root mode.
+pub fn set_confirm_quit(enabled: bool) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.confirm_quit = enabled;
+}
+
+/// Set the bindings of keys to the editing actions of the prompt.
+pub fn set_keymap(keymap: Keymap) {
+    let cli_guard = CLI.lock();
+    let mut cli = cli_guard.borrow_mut();
+    cli.keymap = keymap;
+}
+
+/// Ring the terminal bell, if enabled.
+/// It's never rung out of the interactive shell, so piped output stays plain.
+fn ring_bell(writer: &mut impl Write) {
+    let cli_guard = CLI.lock();
+    let cli = cli_guard.borrow();
+    if cli.bell && cli.interactive {
+        queue!(writer, Print("\x07")).unwrap();
+    }
+}
+
+/// Source of the terminal events handled by the prompt.
+/// The prompt reads events from the terminal by default, see [`TerminalEvents`],
+/// but they can be fed from elsewhere, like synthetic events in tests or events
+/// forwarded from crossterm's async `EventStream` by a runtime that also polls
+/// requests meanwhile.
+pub trait EventSource {
+    /// Wait for the next event.
+    fn read(&mut self) -> std::io::Result<Event>;
+
+    /// Wait up to `timeout` for an event, returning whether one can be read.
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool>;
+}
+
+/// Events read from the terminal, blocking until there is one.
+pub struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn read(&mut self) -> std::io::Result<Event> {
+        event::read()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        event::poll(timeout)
+    }
+}
+
+/// Wait up to `timeout` for the user to press Ctrl+C, discarding any other key meanwhile.
+/// Used to cancel a command that blocks the prompt, like a slow request.
+pub fn wait_ctrl_c(timeout: Duration) -> bool {
+    wait_ctrl_c_from(&mut TerminalEvents, timeout)
+}
+
+/// Same as [`wait_ctrl_c`], with the events read from `events`.
+fn wait_ctrl_c_from(events: &mut impl EventSource, timeout: Duration) -> bool {
+    if !events.poll(timeout).unwrap_or(false) {
+        return false;
+    }
+    matches!(
+        events.read(),
+        Ok(Event::Key(KeyEvent {
+            code: KeyCode::Char('c'),
+            kind: KeyEventKind::Press,
+            modifiers: KeyModifiers::CONTROL,
+            state: _,
+        }))
+    )
+}
+
 /// Print prompt for user input
 /// This will display the configured `prefix>` in a blank line as a shell prompt.
-fn print_prompt() {
-    let mut writer = std::io::stdout();
-    let curr_col = crossterm::cursor::position().unwrap().0;
+fn print_prompt(writer: &mut impl Write) {
+    let curr_col = cursor_position().0;
     if curr_col > 0 {
         queue!(writer, SmartNewLine(1), Clear(ClearType::CurrentLine)).unwrap();
     }
     let cli_guard = CLI.lock();
     let cli = cli_guard.borrow();
-    execute!(
-        writer,
-        PrintStyledContent(cli.prefix.clone()),
-        PrintStyledContent(cli.symbol.clone()),
-    )
-    .unwrap();
+    for piece in cli.prefix_pieces() {
+        queue!(writer, PrintStyled(piece)).unwrap();
+    }
+    execute!(writer, PrintStyled(cli.symbol.clone())).unwrap();
 }
 
 /// Check if we are at the last row in the terminal,
@@ -206,11 +498,21 @@ pub struct SmartNewLine(pub u16);
 /// Implementation of the SmartNewLine that handles next-line + scroll.
 impl crossterm::Command for SmartNewLine {
     fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        if !is_interactive() {
+            // not in raw mode, the terminal or pipe handles new lines itself
+            for _ in 0..self.0 {
+                f.write_char('\n')?;
+            }
+            return Ok(());
+        }
+        // scroll only the rows that would go past the bottom of the terminal
         let curr_row = crossterm::cursor::position().unwrap().1;
         let term_max_row = crossterm::terminal::size().unwrap().1 - 1;
-        if curr_row == term_max_row {
-            ScrollUp(self.0).write_ansi(f)?;
-            MoveUp(self.0).write_ansi(f)?;
+        let overflow = (curr_row + self.0).saturating_sub(term_max_row);
+        if overflow > 0 {
+            ScrollUp(overflow).write_ansi(f)?;
+            MoveUp(overflow).write_ansi(f)?;
+            scrolled_up(overflow);
         }
         MoveToNextLine(self.0).write_ansi(f)?;
         Ok(())
@@ -221,9 +523,10 @@ impl crossterm::Command for SmartNewLine {
         if self.0 != 0 {
             let curr_row = crossterm::cursor::position().unwrap().1;
             let term_max_row = crossterm::terminal::size().unwrap().1 - 1;
-            if curr_row == term_max_row {
-                ScrollUp(self.0).execute_winapi()?;
-                MoveUp(self.0).execute_winapi()?;
+            let overflow = (curr_row + self.0).saturating_sub(term_max_row);
+            if overflow > 0 {
+                ScrollUp(overflow).execute_winapi()?;
+                MoveUp(overflow).execute_winapi()?;
             }
             sys::move_to_previous_line(self.0)?;
         }
@@ -231,500 +534,1818 @@ impl crossterm::Command for SmartNewLine {
     }
 }
 
-/// Read input from terminal until enter is given.
-/// Returns the entered characters until '\n'.
-/// This is a fully featured prompt handling with text manipulation
-/// just like a shell, with history, arrows handling, backspace, alt, ctrl, etc.
-pub fn prompt(cmd_schema: &clap::Command) -> std::io::Result<Vec<String>> {
-    let mut history = HistoryHandle::get();
-    let mut writer = stdout();
-    let mut user_input = String::new();
-    let mut last_prompt: Option<String> = None;
-    let mut suggestion_printed_below = false;
-
-    print_prompt();
-    'prompt_loop: loop {
-        match event::read() {
-            // BACKSPACE
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Backspace,
-                kind: KeyEventKind::Press,
-                modifiers,
-                state: _,
-            })) => {
-                if !user_input.is_empty() {
-                    let count: u16;
-                    if modifiers == KeyModifiers::ALT {
-                        let index = util::str_rfind_last_word_separator(user_input.as_str());
-                        count = (user_input.len() - index) as u16;
-                        // execute!(
-                        //     writer,
-                        //     MoveDown(1),
-                        //     Print(format!("index {} count {}", index, count)),
-                        //     MoveUp(1)
-                        // )
-                        // .unwrap();
-                        _ = user_input.split_off(index);
-                    } else {
-                        user_input.pop();
-                        count = 1;
-                    }
-                    if count > 0 {
-                        execute!(writer, MoveLeft(count), Clear(ClearType::UntilNewLine)).unwrap();
-                    }
-                    if suggestion_printed_below {
-                        clear_line_below(&mut writer);
-                        suggestion_printed_below = false;
-                    }
-                }
-            }
+/// Enable or disable styling of the output, disabled for `NO_COLOR` or `--no-color`.
+pub fn set_color(enabled: bool) {
+    COLOR_ENABLED.store(enabled, Ordering::SeqCst);
+}
 
-            // TAB
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Tab,
-                kind: KeyEventKind::Press,
-                modifiers: _,
-                state: _,
-            })) => {
-                if suggestion_printed_below {
-                    clear_line_below(&mut writer);
-                    suggestion_printed_below = false;
-                }
+/// Print styled content, or only the content when styling is disabled with [`set_color`].
+/// It should be used instead of [`PrintStyledContent`] for all output.
+pub struct PrintStyled<D: Display>(pub StyledContent<D>);
 
-                if user_input.is_empty() {
-                    let cmds = util::get_visible_command_vector(&cmd_schema);
-                    let col = cursor::position().unwrap().0;
-                    queue!(writer, SmartNewLine(1)).unwrap();
-                    print_command_completions(&mut writer, &cmds);
-                    execute!(writer, MoveToPreviousLine(1), MoveToColumn(col)).unwrap();
-                    suggestion_printed_below = true;
-                    continue;
-                }
+/// Implementation of the PrintStyled that falls back to plain content.
+impl<D: Display> crossterm::Command for PrintStyled<D> {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        if COLOR_ENABLED.load(Ordering::SeqCst) {
+            let content = StyledContent::new(*self.0.style(), self.0.content());
+            PrintStyledContent(content).write_ansi(f)
+        } else {
+            write!(f, "{}", self.0.content())
+        }
+    }
 
-                let mut curr_cmd_schema = cmd_schema;
-                let mut user_input_offset = 0;
-                let mut new_user_input = user_input.clone();
-                let user_input2 = user_input.clone();
-                let mut cmd_arg_given = false;
-                for (word_idx, word_input) in user_input2
-                    .split_whitespace()
-                    .map(|str| (str.as_ptr() as usize - user_input2.as_ptr() as usize, str))
-                {
-                    let cmd_arg = curr_cmd_schema.get_arguments().next();
-
-                    let word_input = word_input.to_string();
-                    let has_end_whitespace = user_input2
-                        .chars()
-                        .nth(word_idx + word_input.len())
-                        .map_or_else(|| false, |c| c.is_whitespace());
-
-                    // try to match input string against tree of commands or arguments
-                    let cmd_trie = if cmd_arg.is_some() {
-                        util::get_arg_values_trie(&cmd_arg.unwrap())
-                    } else {
-                        util::get_command_trie(&curr_cmd_schema)
-                    };
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
-                    let cmd_matches = cmd_trie.collect_matches(&word_input);
-                    if cmd_matches.is_empty() || (cmd_matches.len() > 1 && has_end_whitespace) {
-                        let col = cursor::position().unwrap().0;
-                        queue!(writer, SmartNewLine(1)).unwrap();
-                        print_invalid_input(&mut writer, &word_input);
-                        execute!(writer, MoveToPreviousLine(2), MoveToColumn(col)).unwrap();
-                        suggestion_printed_below = true;
-                        continue 'prompt_loop;
-                    }
+/// Move the cursor between two column offsets of the user input.
+/// Offsets are counted from the start of the user input, which is printed
+/// right after the prompt, hence `prompt_len` is the column where offset 0 lies.
+/// Input that wraps past the terminal width spans multiple rows,
+/// so this Command moves up or down as well when the offsets are in different rows.
+pub struct MoveInInput {
+    pub prompt_len: u16,
+    pub from: usize,
+    pub to: usize,
+}
 
-                    // if more than one match then suggest command completion
-                    if cmd_matches.len() > 1 && !has_end_whitespace {
-                        let col = cursor::position().unwrap().0;
-                        queue!(writer, SmartNewLine(1)).unwrap();
-                        print_command_completions(&mut writer, &cmd_matches);
-                        execute!(writer, MoveToPreviousLine(1), MoveToColumn(col)).unwrap();
-                        suggestion_printed_below = true;
-                        continue 'prompt_loop;
-                    }
+impl MoveInInput {
+    /// Compute the (rows up, rows down, column) movement required by this command.
+    fn movement(&self) -> (u16, u16, u16) {
+        let term_width = terminal_width();
+        let from = self.prompt_len as usize + self.from;
+        let to = self.prompt_len as usize + self.to;
+        let (from_row, to_row) = (from / term_width, to / term_width);
+        let up = from_row.saturating_sub(to_row) as u16;
+        let down = to_row.saturating_sub(from_row) as u16;
+        (up, down, (to % term_width) as u16)
+    }
+}
 
-                    // else a full match is found
-                    let cmd = cmd_matches.last().unwrap();
-                    if word_input.len() < cmd.len() {
-                        let word_end_idx = word_idx + word_input.len() + user_input_offset;
-                        let cmd_remainder = cmd.split_at(word_input.len()).1;
-                        user_input_offset += cmd_remainder.len();
-                        new_user_input.insert_str(word_end_idx, cmd_remainder);
-                        // print_prompt_full_completion(&mut writer, &user_input, &word_input, &cmd);
-                    }
+/// Implementation of the MoveInInput that handles wrapped input rows.
+impl crossterm::Command for MoveInInput {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let (up, down, col) = self.movement();
+        // MoveUp(0) and MoveDown(0) still move one row in most terminals
+        if up > 0 {
+            MoveUp(up).write_ansi(f)?;
+        }
+        if down > 0 {
+            MoveDown(down).write_ansi(f)?;
+        }
+        MoveToColumn(col).write_ansi(f)?;
+        Ok(())
+    }
 
-                    // command is final, process it now
-
-                    if cmd_arg.is_some() {
-                        cmd_arg_given = true;
-                    } else {
-                        curr_cmd_schema = curr_cmd_schema
-                            .get_subcommands()
-                            .find(|c| {
-                                c.get_name() == cmd
-                                    || c.get_all_aliases().find(|a| a == cmd) != None
-                            })
-                            .unwrap();
-                    }
-                }
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> std::io::Result<()> {
+        let (up, down, col) = self.movement();
+        if up > 0 {
+            MoveUp(up).execute_winapi()?;
+        }
+        if down > 0 {
+            MoveDown(down).execute_winapi()?;
+        }
+        MoveToColumn(col).execute_winapi()?;
+        Ok(())
+    }
+}
 
-                if user_input.ends_with(" ")
-                    && (curr_cmd_schema.get_subcommands().next().is_some()
-                        || curr_cmd_schema.get_arguments().next().is_some())
-                {
-                    let cmds = if curr_cmd_schema.get_subcommands().next().is_some() {
-                        util::get_visible_command_vector(&curr_cmd_schema)
-                    } else {
-                        util::get_arg_values_vector(curr_cmd_schema.get_arguments().next().unwrap())
-                    };
-                    let col = cursor::position().unwrap().0;
-                    queue!(writer, SmartNewLine(1)).unwrap();
-                    print_command_completions(&mut writer, &cmds);
-                    execute!(writer, MoveToPreviousLine(1), MoveToColumn(col)).unwrap();
-                    suggestion_printed_below = true;
-                    continue 'prompt_loop;
-                }
+/// Position of the cursor in the terminal, as (column, row).
+/// Out of the interactive shell there's no terminal to ask, like when the prompt
+/// is driven by synthetic events in tests, so it's taken to be at the top left.
+fn cursor_position() -> (u16, u16) {
+    if !is_interactive() {
+        return (0, 0);
+    }
+    cursor::position().unwrap()
+}
 
-                if user_input != new_user_input {
-                    execute!(writer, MoveToColumn(0)).unwrap();
-                    print_prompt();
-                    execute!(writer, Print(new_user_input.as_str())).unwrap();
-                    execute!(writer, Print(" ")).unwrap();
-                    user_input = new_user_input.clone();
-                    user_input.push(' ');
-                    continue 'prompt_loop;
-                }
-            }
+/// Width of the terminal in columns, or 80 out of the interactive shell,
+/// see [`cursor_position`], or if the terminal can't tell its size.
+fn terminal_width() -> usize {
+    if !is_interactive() {
+        return 80;
+    }
+    terminal::size().map_or(80, |(cols, _)| cols.max(1) as usize)
+}
 
-            // ENTER
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Enter,
-                kind: KeyEventKind::Press,
-                modifiers: _,
-                state: _,
-            })) => {
-                if suggestion_printed_below {
-                    clear_line_below(&mut writer);
-                    suggestion_printed_below = false;
-                }
-                if user_input.is_empty() {
-                    print_prompt();
-                    continue;
-                }
-                let mut args = Vec::new();
-                let mut curr_cmd_schema = cmd_schema;
-                let mut user_input_offset = 0;
-                let mut new_user_input = user_input.clone();
-                let user_input2 = user_input.clone();
-                let mut cmd_arg_given = false;
-                for (word_idx, word_input) in user_input2
-                    .split_whitespace()
-                    .map(|str| (str.as_ptr() as usize - user_input2.as_ptr() as usize, str))
-                {
-                    let cmd_arg = curr_cmd_schema.get_arguments().next();
-                    if cmd_arg.is_some() && cmd_arg.unwrap().get_possible_values().is_empty() {
-                        args.push(word_input.to_string());
-                        cmd_arg_given = true;
-                        continue;
-                    }
+/// Length of the prompt `prefix>` in terminal columns.
+/// The user input starts just after it.
+fn prompt_len() -> u16 {
+    let cli_guard = CLI.lock();
+    let cli = cli_guard.borrow();
+    (input_width(&get_prefix()) + input_width(cli.symbol.content())) as u16
+}
 
-                    let word_input = word_input.to_string();
-                    let has_end_whitespace = user_input2
-                        .chars()
-                        .nth(word_idx + word_input.len())
-                        .map_or_else(|| false, |c| c.is_whitespace());
-
-                    // try to match input string against tree of commands or arguments
-                    let cmd_trie = if cmd_arg.is_some() {
-                        util::get_arg_values_trie(&cmd_arg.unwrap())
-                    } else {
-                        util::get_command_trie(&curr_cmd_schema)
-                    };
+/// Number of terminal columns taken by a piece of user input.
+/// Wide characters such as CJK and emoji take two columns.
+fn input_width(input: &str) -> usize {
+    input.width()
+}
 
-                    let cmd_matches = cmd_trie.collect_matches(&word_input);
-                    if cmd_matches.is_empty() || (cmd_matches.len() > 1 && has_end_whitespace) {
-                        queue!(writer, SmartNewLine(1)).unwrap();
-                        print_invalid_input(&mut writer, &word_input);
-                        print_prompt();
-                        history.add(new_user_input);
-                        user_input.clear();
-                        continue 'prompt_loop;
-                    }
+/// Print the user input from byte index `idx` until its end.
+/// The cursor is left right after the last character of the input.
+/// When the input ends exactly at the terminal width, the terminal holds the cursor
+/// at the last column instead of wrapping it, so a blank is printed and erased
+/// to force the cursor into the next row and keep `MoveInInput` offsets correct.
+fn print_input_tail(writer: &mut impl Write, prompt_len: u16, user_input: &str, idx: usize) {
+    let term_width = terminal_width();
+    queue!(writer, Print(&user_input[idx..])).unwrap();
+    let end = prompt_len as usize + input_width(user_input);
+    if idx < user_input.len() && end % term_width == 0 {
+        queue!(writer, Print(" "), MoveLeft(1)).unwrap();
+    }
+}
 
-                    // if more than one match then suggest command completion
-                    if cmd_matches.len() > 1 && !has_end_whitespace {
-                        queue!(writer, SmartNewLine(1)).unwrap();
-                        print_command_completions(&mut writer, &cmd_matches);
-                        print_prompt();
-                        execute!(writer, Print(user_input.as_str())).unwrap();
-                        continue 'prompt_loop;
-                    }
+/// Candidates of a completion cycled through with repeated Tab presses.
+/// The word being completed starts at byte index `word_idx` of the user input
+/// and runs until its end, it's replaced by each candidate in turn.
+struct CompletionCycle {
+    candidates: Vec<String>,
+    /// index of the candidate in the input, none before the first Tab press of the cycle
+    index: Option<usize>,
+    word_idx: usize,
+}
 
-                    // else a full match is found
-                    let cmd = cmd_matches.last().unwrap();
-                    if word_input.len() < cmd.len() {
-                        let word_end_idx = word_idx + word_input.len() + user_input_offset;
-                        let cmd_remainder = cmd.split_at(word_input.len()).1;
-                        user_input_offset += cmd_remainder.len();
-                        new_user_input.insert_str(word_end_idx, cmd_remainder);
-                        // print_prompt_full_completion(&mut writer, &user_input, &word_input, &cmd);
-                    }
+impl CompletionCycle {
+    fn new(candidates: Vec<String>, word_idx: usize) -> Option<Self> {
+        (!candidates.is_empty()).then_some(Self {
+            candidates,
+            index: None,
+            word_idx,
+        })
+    }
 
-                    // command is final, process it now
-                    args.push(cmd.clone());
-
-                    if cmd_arg.is_some() {
-                        cmd_arg_given = true;
-                    } else {
-                        curr_cmd_schema = curr_cmd_schema
-                            .get_subcommands()
-                            .find(|c| {
-                                c.get_name() == cmd
-                                    || c.get_all_aliases().find(|a| a == cmd) != None
-                            })
-                            .unwrap();
-                    }
-                }
-                execute!(writer, MoveToColumn(0)).unwrap();
-                print_prompt();
-                execute!(writer, Print(new_user_input.as_str())).unwrap();
-                // clear any previous line of command suggestions
-                execute!(writer, SmartNewLine(1), Clear(ClearType::CurrentLine)).unwrap();
-                history.add(new_user_input.trim().to_string());
-
-                let cli_arg = curr_cmd_schema.get_arguments().next();
-                if cli_arg.is_some() && cli_arg.unwrap().is_required_set() && !cmd_arg_given {
-                    cliprintln!(writer, "Missing argument");
-                    print_prompt();
-                    user_input.clear();
-                    continue;
-                }
+    /// Move on to the next candidate, or the previous one if `backwards`, wrapping around.
+    fn next(&mut self, backwards: bool) -> &str {
+        let len = self.candidates.len();
+        let index = match (self.index, backwards) {
+            (None, false) => 0,
+            (None, true) => len - 1,
+            (Some(index), false) => (index + 1) % len,
+            (Some(index), true) => (index + len - 1) % len,
+        };
+        self.index = Some(index);
+        &self.candidates[index]
+    }
+}
 
-                return Ok(args);
-            }
+/// Ask a yes/no question and read the answer from a single keypress, without waiting for Enter.
+/// Only `y` answers yes, any other key like Enter or Esc answers the default no.
+/// Without a terminal to read the answer from, like when running a script, it's always no.
+pub fn confirm(question: &str) -> std::io::Result<bool> {
+    confirm_from(&mut stdout(), &mut TerminalEvents, question)
+}
 
-            // CTRL + C
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char('c'),
+/// Same as [`confirm`], with the answer read from `events`.
+fn confirm_from(
+    writer: &mut impl Write,
+    events: &mut impl EventSource,
+    question: &str,
+) -> std::io::Result<bool> {
+    cliprint!(writer, "{} [y/N] ", question)?;
+    let mut answer = false;
+    if is_interactive() {
+        answer = loop {
+            if let Event::Key(KeyEvent {
+                code,
                 kind: KeyEventKind::Press,
-                modifiers: KeyModifiers::CONTROL,
-                state: _,
-            })) => {
-                execute!(writer, Print("^C"), SmartNewLine(1)).unwrap();
-                print_prompt();
-                user_input.clear();
+                ..
+            }) = events.read()?
+            {
+                break matches!(code, KeyCode::Char('y' | 'Y'));
             }
+        };
+    }
+    cliprintln!(writer, "{}", if answer { "y" } else { "n" })?;
+    Ok(answer)
+}
 
-            // CTRL + D
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char('d'),
-                kind: KeyEventKind::Press,
-                modifiers: KeyModifiers::CONTROL,
-                state: _,
-            })) => {
-                if user_input.is_empty() {
-                    execute!(writer, Print("^D"), SmartNewLine(1)).unwrap();
-                    return Ok(vec![String::from("exit")]);
-                }
-            }
+/// Read a text of multiple lines, like a long review message, typed line by line.
+/// ENTER starts a new line, and CTRL+D or a line with a lone `.` ends the text.
+/// BACKSPACE erases within the line being typed, lines already entered are kept.
+/// CTRL+C discards the text and gives `None`.
+/// It fails without a terminal to read the text from, like when running a script.
+pub fn read_text() -> std::io::Result<Option<String>> {
+    if !is_interactive() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "no terminal to read the text from",
+        ));
+    }
+    read_text_from(&mut stdout(), &mut TerminalEvents)
+}
 
-            // CTRL + L
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char('l'),
-                kind: KeyEventKind::Press,
-                modifiers: KeyModifiers::CONTROL,
-                state: _,
-            })) => {
-                let curr_row = crossterm::cursor::position().unwrap().1;
-                execute!(writer, ScrollUp(curr_row), MoveUp(curr_row)).unwrap()
+/// Same as [`read_text`], with the keys read from `events`.
+fn read_text_from(
+    writer: &mut impl Write,
+    events: &mut impl EventSource,
+) -> std::io::Result<Option<String>> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    loop {
+        let Event::Key(key_event) = events.read()? else {
+            continue;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            continue;
+        }
+        let ctrl = key_event.modifiers == KeyModifiers::CONTROL;
+        match key_event.code {
+            KeyCode::Char('c') if ctrl => {
+                execute!(writer, Print("^C"), SmartNewLine(1))?;
+                return Ok(None);
             }
-
-            // ARROW UP
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Up,
-                kind: KeyEventKind::Press,
-                modifiers: _,
-                state: _,
-            })) => {
-                if let Some(up_next) = history.up_next() {
-                    let count = user_input.len() as u16;
-                    if last_prompt == None {
-                        last_prompt = Some(user_input.clone())
-                    }
-                    user_input = up_next;
-                    if count > 0 {
-                        execute!(writer, MoveLeft(count), Clear(ClearType::UntilNewLine),).unwrap();
-                    }
-                    execute!(writer, Print(user_input.as_str())).unwrap();
+            KeyCode::Char('d') if ctrl => {
+                execute!(writer, SmartNewLine(1))?;
+                if !line.is_empty() {
+                    lines.push(line);
                 }
+                break;
             }
-
-            // ARROW DOWN
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Down,
-                kind: KeyEventKind::Press,
-                modifiers: _,
-                state: _,
-            })) => {
-                if let Some(down_next) = history.down_next() {
-                    let count = user_input.len() as u16;
-                    user_input = down_next;
-                    if count > 0 {
-                        execute!(writer, MoveLeft(count), Clear(ClearType::UntilNewLine)).unwrap();
-                    }
-                    execute!(writer, Print(user_input.as_str())).unwrap();
-                } else {
-                    let count = user_input.len() as u16;
-                    if count > 0 {
-                        execute!(writer, MoveLeft(count), Clear(ClearType::UntilNewLine),).unwrap();
-                    }
-                    if last_prompt.is_some() {
-                        user_input = last_prompt.unwrap();
-                        last_prompt = None;
-                    }
-                    execute!(writer, Print(user_input.as_str())).unwrap();
+            KeyCode::Enter => {
+                execute!(writer, SmartNewLine(1))?;
+                if line == "." {
+                    break;
                 }
+                lines.push(std::mem::take(&mut line));
             }
-
-            // CHARACTERS
-            Ok(Event::Key(KeyEvent {
-                code: KeyCode::Char(c),
-                kind: KeyEventKind::Press,
-                modifiers: _,
-                state: _,
-            })) => {
-                execute!(writer, Print(c)).unwrap();
-                user_input.push(c);
+            KeyCode::Backspace => {
+                if let Some(idx) = util::prev_grapheme_idx(&line, line.len()) {
+                    let movement = MoveInInput {
+                        prompt_len: 0,
+                        from: input_width(&line),
+                        to: input_width(&line[..idx]),
+                    };
+                    line.truncate(idx);
+                    execute!(writer, movement, Clear(ClearType::FromCursorDown))?;
+                }
+            }
+            KeyCode::Char(c) => {
+                line.push(c);
+                execute!(writer, Print(c))?;
             }
-
-            // ANYTHING
             _ => {}
         }
     }
+    Ok(Some(lines.join("\n")))
 }
 
-/// Print out list of commands as for completion suggestions.
-/// TODO: support line wrapping after newline tracking is implemented.
-fn print_command_completions(writer: &mut impl Write, cmds: &Vec<String>) {
-    for cmd in cmds {
-        queue!(writer, Print(cmd), Print("  ")).unwrap();
+/// Open the editor of the user on a temporary file holding `initial`, and give back
+/// the text of the file once the editor exits, like git does for commit messages.
+/// The editor is taken from `VISUAL` or `EDITOR`, and is `vi` if none is set,
+/// or `notepad` on Windows. It fails if the editor exits with an error.
+/// Raw mode is suspended while the editor runs, and the temporary file is removed afterwards.
+pub fn edit_in_editor(initial: &str) -> std::io::Result<String> {
+    static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+    let file_name = format!(
+        "gerrit-edit-{}-{}.txt",
+        std::process::id(),
+        FILE_COUNT.fetch_add(1, Ordering::SeqCst)
+    );
+    let path = std::env::temp_dir().join(file_name);
+    std::fs::write(&path, initial)?;
+    let text = run_editor(&path).and_then(|()| std::fs::read_to_string(&path));
+    _ = std::fs::remove_file(&path);
+    text
+}
+
+/// Run the editor of the user on a file and wait for it to exit, see [`edit_in_editor`].
+/// The editor may be given with arguments, like `code --wait`.
+fn run_editor(path: &Path) -> std::io::Result<()> {
+    let default_editor = if cfg!(windows) { "notepad" } else { "vi" };
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| default_editor.to_string());
+    let words = util::split_words(&editor).map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid editor '{}': {}", editor, err),
+        )
+    })?;
+    let mut words = words.into_iter().map(|word| word.value);
+    let program = words.next().unwrap_or_default();
+    let status = run_program(std::process::Command::new(&program).args(words).arg(path))?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} exited with {}",
+            program, status
+        )));
     }
+    Ok(())
 }
 
-/// Complete user prompt with remainder of command string
-/// This will print only remaining characters.
-fn print_prompt_full_completion(
-    writer: &mut impl Write,
-    user_input: &String,
-    trimmed_input: &String,
-    cmd: &String,
-) {
-    let whitespace_count = user_input.trim_start().len() - trimmed_input.len();
-    if whitespace_count > 0 {
-        queue!(writer, MoveLeft(whitespace_count as u16),).unwrap();
+/// Run a program on the terminal and wait for it to exit, with raw mode suspended meanwhile
+/// so the program reads and prints as usual. A failure to start names the program.
+pub fn run_program(
+    command: &mut std::process::Command,
+) -> std::io::Result<std::process::ExitStatus> {
+    let _raw_mode = RawModeSuspended::new();
+    command.status().map_err(|err| {
+        let program = command.get_program().to_string_lossy();
+        std::io::Error::new(err.kind(), format!("{}: {}", program, err))
+    })
+}
+
+/// Raw mode suspended for as long as this is alive, so a program run
+/// in the meantime gets the terminal as it expects it.
+/// Raw mode is enabled again when dropped, however the program ended.
+struct RawModeSuspended {
+    was_enabled: bool,
+}
+
+impl RawModeSuspended {
+    fn new() -> Self {
+        let was_enabled = RAW_MODE_ENABLED.load(Ordering::SeqCst);
+        if was_enabled {
+            _ = terminal::disable_raw_mode();
+        }
+        Self { was_enabled }
     }
-    queue!(writer, Print(cmd.split_at(trimmed_input.len()).1)).unwrap();
 }
 
-/// Clear line below and return to previous line
-fn clear_line_below(writer: &mut impl Write) {
-    execute!(
-        writer,
-        MoveDown(1),
-        Clear(ClearType::CurrentLine),
-        MoveUp(1)
-    )
-    .unwrap();
+impl Drop for RawModeSuspended {
+    fn drop(&mut self) {
+        if self.was_enabled {
+            _ = terminal::enable_raw_mode();
+        }
+    }
 }
 
-/// Print out message "Unknown command" with new line
-fn print_invalid_input(writer: &mut impl Write, input: &str) {
-    execute!(
-        writer,
-        PrintStyledContent("x".red()),
-        Print(" Invalid input: "),
-        Print(input),
-        SmartNewLine(1)
-    )
-    .unwrap();
+/// Read input from terminal until enter is given.
+/// Returns the entered characters until '\n'.
+/// This is a fully featured prompt handling with text manipulation
+/// just like a shell, with history, arrows handling, backspace, alt, ctrl, etc.
+pub fn prompt(cmd_schema: &clap::Command) -> std::io::Result<Vec<String>> {
+    Prompt::new(cmd_schema, stdout(), TerminalEvents).read_line()
 }
 
-struct Prompt {
-    writer: Stdout,
+/// State of the prompt while a line of input is read, see [`prompt`].
+/// Each key handled has its own method, which edits the input and reprints it.
+/// Events are read from `events` and all output goes to `writer`, so the prompt
+/// can be driven without a terminal.
+struct Prompt<'a, W: Write, E: EventSource> {
+    cmd_schema: &'a clap::Command,
+    events: E,
+    writer: W,
     history: HistoryHandle,
     user_input: String,
-    last_prompt: Option<String>,
     suggestion_printed_below: bool,
+    /// byte index in `user_input` where the cursor is at
+    cursor_idx: usize,
+    completion_cycle: Option<CompletionCycle>,
+    prompt_len: u16,
+    keymap: Keymap,
+    /// tries to complete the input with, built once for the prompt of the line
+    tries: CompletionTries<'a>,
 }
 
-impl Prompt {
-    pub fn new() -> Self {
+impl<'a, W: Write, E: EventSource> Prompt<'a, W, E> {
+    fn new(cmd_schema: &'a clap::Command, writer: W, events: E) -> Self {
         Self {
-            writer: stdout(),
+            cmd_schema,
+            events,
+            writer,
             history: HistoryHandle::get(),
             user_input: String::new(),
-            last_prompt: None,
             suggestion_printed_below: false,
+            cursor_idx: 0,
+            completion_cycle: None,
+            prompt_len: prompt_len(),
+            keymap: CLI.lock().borrow().keymap.clone(),
+            tries: CompletionTries::default(),
         }
     }
 
-    pub fn prompt(&mut self) -> std::io::Result<Vec<String>> {
+    /// Print the prompt and handle events until a line of input is entered.
+    fn read_line(&mut self) -> std::io::Result<Vec<String>> {
+        print_prompt(&mut self.writer);
         loop {
-            let control_flow = match event::read()? {
-                Event::Key(event) => self.key_event(event),
-                _ => ControlFlow::Continue(()),
+            let control_flow = match self.events.read() {
+                Ok(Event::Key(key_event)) if key_event.kind == KeyEventKind::Press => {
+                    self.key_event(key_event)?
+                }
+                Ok(Event::Resize(cols, _)) => {
+                    self.resize(cols);
+                    ControlFlow::Continue(())
+                }
+                Ok(_) => ControlFlow::Continue(()),
+                Err(err) => return Err(err),
             };
-            if let ControlFlow::Break(input) = control_flow {
-                return Ok(input);
+            if let ControlFlow::Break(args) = control_flow {
+                return Ok(args);
             }
         }
     }
 
-    fn key_event(&mut self, event: KeyEvent) -> ControlFlow<Vec<String>> {
-        match event {
-            KeyEvent {
-                code: KeyCode::Backspace,
-                kind: KeyEventKind::Press,
-                ..
-            } => self.backspace(event),
-            _ => ControlFlow::Continue(()),
+    /// Dispatch a key press to the handler of the action it's bound to in the keymap,
+    /// or insert the character typed if it's bound to none.
+    fn key_event(&mut self, key_event: KeyEvent) -> std::io::Result<ControlFlow<Vec<String>>> {
+        let action = self.keymap.action(&key_event);
+        // any action other than completing ends the cycling through completions
+        if !matches!(action, Some(Action::Complete | Action::CompleteBackwards)) {
+            self.completion_cycle = None;
+        }
+        match action {
+            Some(Action::Submit) => return Ok(self.enter()),
+            Some(Action::Quit) if self.user_input.is_empty() => return Ok(self.exit()),
+            Some(Action::Quit) | Some(Action::Delete) => self.delete(),
+            Some(Action::Cancel) => self.interrupt(),
+            Some(Action::Backspace) => self.backspace(false),
+            Some(Action::BackspaceWord) => self.backspace(true),
+            Some(Action::Complete) => self.tab(false),
+            Some(Action::CompleteBackwards) => self.tab(true),
+            Some(Action::ClearScreen) => self.clear_screen(),
+            Some(Action::HistoryUp) => self.history_up(),
+            Some(Action::HistoryDown) => self.history_down(),
+            Some(Action::ReverseSearch) => self.reverse_search()?,
+            Some(Action::Left) => self.left(false),
+            Some(Action::Right) => self.right(false),
+            Some(Action::WordLeft) => self.left(true),
+            Some(Action::WordRight) => self.right(true),
+            Some(Action::Home) => self.home(),
+            Some(Action::End) => self.end(),
+            Some(Action::KillToStart) => self.kill_to_start(),
+            Some(Action::KillLine) => self.kill_to_end(),
+            None => {
+                if let KeyCode::Char(c) = key_event.code {
+                    self.insert(c);
+                }
+            }
         }
+        Ok(ControlFlow::Continue(()))
     }
 
-    fn backspace(&mut self, event: KeyEvent) -> ControlFlow<Vec<String>> {
+    /// Move the cursor to byte index `idx` of the input.
+    fn move_cursor(&mut self, idx: usize) {
+        execute!(
+            self.writer,
+            MoveInInput {
+                prompt_len: self.prompt_len,
+                from: input_width(&self.user_input[..self.cursor_idx]),
+                to: input_width(&self.user_input[..idx]),
+            }
+        )
+        .unwrap();
+        self.cursor_idx = idx;
+    }
+
+    /// Clear the suggestions printed below the input, if any.
+    fn clear_suggestions(&mut self) {
+        if self.suggestion_printed_below {
+            clear_below_input(
+                &mut self.writer,
+                self.prompt_len,
+                &self.user_input,
+                self.cursor_idx,
+            );
+            self.suggestion_printed_below = false;
+        }
+    }
+
+    /// Replace the whole input with a new one and leave the cursor at its end.
+    fn replace_input(&mut self, new_input: String) {
+        let cursor_col = input_width(&self.user_input[..self.cursor_idx]);
+        self.user_input = new_input;
+        reprint_input(
+            &mut self.writer,
+            self.prompt_len,
+            cursor_col,
+            &self.user_input,
+        );
+        self.cursor_idx = self.user_input.len();
+    }
+
+    /// Start over with an empty input, after the prompt is printed again.
+    fn clear_input(&mut self) {
+        self.user_input.clear();
+        self.cursor_idx = 0;
+    }
+
+    /// Reprint the input from the cursor on, after the input was edited there,
+    /// and move the cursor back to where it was.
+    fn reprint_tail(&mut self) {
+        print_input_tail(
+            &mut self.writer,
+            self.prompt_len,
+            &self.user_input,
+            self.cursor_idx,
+        );
+        execute!(
+            self.writer,
+            Clear(ClearType::FromCursorDown),
+            MoveInInput {
+                prompt_len: self.prompt_len,
+                from: input_width(&self.user_input),
+                to: input_width(&self.user_input[..self.cursor_idx]),
+            }
+        )
+        .unwrap();
+    }
+
+    /// BACKSPACE erases the character before the cursor, ALT+BACKSPACE the word before it.
+    fn backspace(&mut self, by_word: bool) {
+        if self.cursor_idx == 0 {
+            return;
+        }
+        let index = if by_word {
+            util::str_rfind_last_word_separator(&self.user_input[..self.cursor_idx])
+        } else {
+            util::prev_grapheme_idx(&self.user_input, self.cursor_idx).unwrap()
+        };
+        if index < self.cursor_idx {
+            let erased_end = self.cursor_idx;
+            self.move_cursor(index);
+            self.user_input.replace_range(index..erased_end, "");
+            self.reprint_tail();
+        }
+        self.clear_suggestions();
+    }
+
+    /// TAB completes the input, or lists the candidates to complete it with.
+    /// Repeated TAB, or SHIFT+TAB backwards, cycles through the candidates listed.
+    fn tab(&mut self, backwards: bool) {
+        // repeated tab replaces the word being completed with the next candidate
+        if let Some(cycle) = &mut self.completion_cycle {
+            let word_idx = cycle.word_idx;
+            let candidate = cycle.next(backwards).to_string();
+            self.move_cursor(word_idx);
+            self.user_input.replace_range(word_idx.., &candidate);
+            self.cursor_idx = self.user_input.len();
+            print_input_tail(
+                &mut self.writer,
+                self.prompt_len,
+                &self.user_input,
+                word_idx,
+            );
+            execute!(self.writer, Clear(ClearType::UntilNewLine)).unwrap();
+            return;
+        }
+
+        self.clear_suggestions();
+        // completion works on the whole input, so continue from its end
+        self.move_cursor(self.user_input.len());
+
+        match complete(
+            self.cmd_schema,
+            &mut self.tries,
+            &self.user_input,
+            self.cursor_idx,
+        ) {
+            Completion::None => {}
+            Completion::Invalid(word) => {
+                let col = cursor_position().0;
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                print_invalid_input(&mut self.writer, &word);
+                execute!(self.writer, MoveToPreviousLine(2), MoveToColumn(col)).unwrap();
+                self.suggestion_printed_below = true;
+            }
+            Completion::Replace {
+                input,
+                cursor: new_cursor_idx,
+            } => {
+                self.replace_input(input);
+                self.move_cursor(new_cursor_idx);
+            }
+            Completion::Candidates {
+                completions,
+                candidates,
+                word_idx,
+            } => {
+                let col = cursor_position().0;
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                let rows = print_command_completions(&mut self.writer, completions, &candidates);
+                execute!(self.writer, MoveToPreviousLine(rows), MoveToColumn(col)).unwrap();
+                self.suggestion_printed_below = true;
+                self.completion_cycle = CompletionCycle::new(candidates, word_idx);
+            }
+        }
+    }
+
+    /// ENTER parses the input into the args of a command, completing its words.
+    /// The line is entered only if it's valid, otherwise the error is printed out
+    /// and the prompt starts over.
+    fn enter(&mut self) -> ControlFlow<Vec<String>> {
+        self.clear_suggestions();
         if self.user_input.is_empty() {
+            print_prompt(&mut self.writer);
             return ControlFlow::Continue(());
         }
-        let num_of_chars_to_clear: u16;
-        if event.modifiers == KeyModifiers::ALT {
-            if let Some(idx) = self.user_input.rfind(" ") {
-                // TODO: fix line wrap and overflow
-                num_of_chars_to_clear = (self.user_input.len() - idx) as u16;
-                _ = self.user_input.split_off(idx);
+        self.move_cursor(self.user_input.len());
+        // the input as typed, which is on screen until it's echoed back below
+        let typed_width = input_width(&self.user_input);
+        // decided on the input as typed, a leading space may keep it out of history
+        let record_history = !history::is_ignored(&self.user_input);
+        // expand history designators `!!` and `!N` before parsing
+        let trimmed_input = self.user_input.trim();
+        if trimmed_input.starts_with('!')
+            && (trimmed_input == "!!" || trimmed_input[1..].parse::<usize>().is_ok())
+        {
+            if let Some(expanded) = self.history.expand(trimmed_input) {
+                self.user_input = expanded;
+                self.cursor_idx = self.user_input.len();
+            } else {
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                print_invalid_input(&mut self.writer, trimmed_input);
+                print_prompt(&mut self.writer);
+                self.clear_input();
+                return ControlFlow::Continue(());
+            }
+        }
+        let mut args = Vec::new();
+        let mut curr_cmd_schema = self.cmd_schema;
+        let mut user_input_offset = 0;
+        let mut new_user_input = self.user_input.clone();
+        let user_input = self.user_input.clone();
+        let mut positionals_given = 0;
+        // option given in the previous words, with how many values it took so far
+        let mut pending_option: Option<(&clap::Arg, usize)> = None;
+        // quoted text is kept in one argument, like a message
+        let words = match util::split_words(&user_input) {
+            Ok(words) => words,
+            Err(err) => {
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                print_invalid_input(&mut self.writer, &err);
+                print_prompt(&mut self.writer);
+                if record_history {
+                    self.history.add(new_user_input);
+                }
+                self.clear_input();
+                return ControlFlow::Continue(());
+            }
+        };
+        for InputWord {
+            span,
+            value: word_input,
+        } in words
+        {
+            // options of the command are passed through as given, with their values,
+            // an option taking several values takes the words until the next option
+            if let Some((option, given)) = pending_option.take() {
+                if given == 0 || util::find_option(curr_cmd_schema, &word_input).is_none() {
+                    if given + 1 < util::option_max_values(option) {
+                        pending_option = Some((option, given + 1));
+                    }
+                    args.push(word_input);
+                    continue;
+                }
+            }
+            if let Some(option) = util::find_option(curr_cmd_schema, &word_input) {
+                if util::option_max_values(option) > 0 {
+                    pending_option = Some((option, 0));
+                }
+                args.push(word_input);
+                continue;
+            }
+
+            let cmd_arg = util::positional_arg(curr_cmd_schema, positionals_given);
+            if cmd_arg.is_some_and(|arg| arg.get_possible_values().is_empty()) {
+                args.push(word_input);
+                positionals_given += 1;
+                continue;
+            }
+
+            let has_end_whitespace = user_input[span.end..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_whitespace());
+
+            // try to match input string against tree of commands or arguments
+            let cmd_trie = match cmd_arg {
+                Some(arg) => self.tries.arg_values_trie(arg),
+                None => self.tries.command_trie(curr_cmd_schema),
+            };
+
+            let mut cmd_matches = cmd_trie.collect_matches(&word_input);
+            // a complete word equal to a candidate is not ambiguous with longer ones
+            if cmd_matches.contains(&word_input) {
+                cmd_matches = vec![word_input.clone()];
+            }
+            if cmd_matches.is_empty() || (cmd_matches.len() > 1 && has_end_whitespace) {
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                print_invalid_input(&mut self.writer, &word_input);
+                print_prompt(&mut self.writer);
+                if record_history {
+                    self.history.add(new_user_input);
+                }
+                self.clear_input();
+                return ControlFlow::Continue(());
+            }
+
+            // if more than one match then suggest command completion
+            if cmd_matches.len() > 1 {
+                queue!(self.writer, SmartNewLine(1)).unwrap();
+                let completions = match cmd_arg {
+                    Some(arg) => Completions::ArgValues(arg),
+                    None => Completions::Commands(curr_cmd_schema),
+                };
+                print_command_completions(&mut self.writer, completions, &cmd_matches);
+                print_prompt(&mut self.writer);
+                execute!(self.writer, Print(self.user_input.as_str())).unwrap();
+                return ControlFlow::Continue(());
+            }
+
+            // else a full match is found
+            let cmd = cmd_matches.last().unwrap();
+            if word_input.len() < cmd.len() {
+                let word_end_idx = span.end + user_input_offset;
+                let cmd_remainder = cmd.split_at(word_input.len()).1;
+                user_input_offset += cmd_remainder.len();
+                new_user_input.insert_str(word_end_idx, cmd_remainder);
+            }
+
+            // command is final, process it now
+            args.push(cmd.clone());
+
+            if cmd_arg.is_some() {
+                positionals_given += 1;
             } else {
-                num_of_chars_to_clear = self.user_input.len() as u16;
-                self.user_input.clear();
+                curr_cmd_schema = util::find_subcommand(curr_cmd_schema, cmd).unwrap();
+                positionals_given = 0;
             }
+        }
+        // the echoed input may differ from what was typed, e.g. `!!` is expanded,
+        // reprinting it also clears any previous line of command suggestions
+        reprint_input(
+            &mut self.writer,
+            self.prompt_len,
+            typed_width,
+            &new_user_input,
+        );
+        execute!(self.writer, SmartNewLine(1)).unwrap();
+        if record_history {
+            self.history.add(new_user_input.trim().to_string());
+        }
+
+        let cli_arg = util::positional_arg(curr_cmd_schema, positionals_given);
+        if cli_arg.is_some_and(|arg| arg.is_required_set()) {
+            cliprintln!(self.writer, "Missing argument").unwrap();
+            print_prompt(&mut self.writer);
+            self.clear_input();
+            return ControlFlow::Continue(());
+        }
+
+        ControlFlow::Break(args)
+    }
+
+    /// CTRL+C drops the input and starts over with a new prompt.
+    fn interrupt(&mut self) {
+        execute!(self.writer, Print("^C"), SmartNewLine(1)).unwrap();
+        print_prompt(&mut self.writer);
+        self.clear_input();
+    }
+
+    /// CTRL+D on an empty input exits, like a shell:
+    /// within a mode it's the same as `exit`, going back one mode,
+    /// and at the root it quits, asking first if enabled with [`set_confirm_quit`].
+    fn exit(&mut self) -> ControlFlow<Vec<String>> {
+        execute!(self.writer, Print("^D"), SmartNewLine(1)).unwrap();
+        let (at_root, confirm_quit) = {
+            let cli_guard = CLI.lock();
+            let cli = cli_guard.borrow();
+            (cli.modes.is_empty(), cli.confirm_quit)
+        };
+        if at_root
+            && confirm_quit
+            && !confirm_from(&mut self.writer, &mut self.events, "Really quit?").unwrap_or(false)
+        {
+            print_prompt(&mut self.writer);
+            self.clear_input();
+            return ControlFlow::Continue(());
+        }
+        ControlFlow::Break(vec![String::from("exit")])
+    }
+
+    /// DELETE, or CTRL+D, erases the character under the cursor.
+    fn delete(&mut self) {
+        if let Some(next_idx) = util::next_grapheme_idx(&self.user_input, self.cursor_idx) {
+            self.user_input.replace_range(self.cursor_idx..next_idx, "");
+            self.reprint_tail();
+            self.clear_suggestions();
+        }
+    }
+
+    /// Redraw the prompt and input after the terminal is resized to `cols` columns.
+    fn resize(&mut self, cols: u16) {
+        // the input is wrapped into rows of the new width, so redraw it from the prompt
+        // start, which is found assuming the terminal reflowed the input to the new width
+        let cursor_offset =
+            self.prompt_len as usize + input_width(&self.user_input[..self.cursor_idx]);
+        let rows_up = (cursor_offset / cols.max(1) as usize) as u16;
+        if rows_up > 0 {
+            queue!(self.writer, MoveUp(rows_up)).unwrap();
+        }
+        execute!(
+            self.writer,
+            MoveToColumn(0),
+            Clear(ClearType::FromCursorDown)
+        )
+        .unwrap();
+        self.suggestion_printed_below = false;
+        self.completion_cycle = None;
+        print_prompt(&mut self.writer);
+        print_input_tail(&mut self.writer, self.prompt_len, &self.user_input, 0);
+        execute!(
+            self.writer,
+            MoveInInput {
+                prompt_len: self.prompt_len,
+                from: input_width(&self.user_input),
+                to: input_width(&self.user_input[..self.cursor_idx]),
+            }
+        )
+        .unwrap();
+    }
+
+    /// CTRL+L scrolls the prompt up to the top of the screen.
+    fn clear_screen(&mut self) {
+        let curr_row = cursor_position().1;
+        execute!(self.writer, ScrollUp(curr_row), MoveUp(curr_row)).unwrap();
+        scrolled_up(curr_row);
+    }
+
+    /// ARROW UP replaces the input with the previous line in history.
+    fn history_up(&mut self) {
+        if let Some(up_next) = self.history.up_next(&self.user_input) {
+            self.replace_input(up_next);
+        }
+    }
+
+    /// ARROW DOWN replaces the input with the next line in history,
+    /// or with the input typed before browsing the history.
+    fn history_down(&mut self) {
+        if let Some(down_next) = self.history.down_next() {
+            self.replace_input(down_next);
+        }
+    }
+
+    /// ARROW LEFT moves the cursor one character left, with ALT or CTRL
+    /// to the start of the previous word.
+    fn left(&mut self, by_word: bool) {
+        let prev_idx = if by_word {
+            Some(util::str_rfind_last_word_separator(
+                &self.user_input[..self.cursor_idx],
+            ))
+            .filter(|idx| *idx < self.cursor_idx)
         } else {
-            self.user_input.pop();
-            num_of_chars_to_clear = 1;
+            util::prev_grapheme_idx(&self.user_input, self.cursor_idx)
+        };
+        if let Some(prev_idx) = prev_idx {
+            self.move_cursor(prev_idx);
         }
+    }
+
+    /// ARROW RIGHT moves the cursor one character right, with ALT or CTRL
+    /// to the end of the next word.
+    fn right(&mut self, by_word: bool) {
+        let next_idx = if by_word {
+            Some(
+                self.cursor_idx
+                    + util::str_find_next_word_separator(&self.user_input[self.cursor_idx..]),
+            )
+            .filter(|idx| *idx > self.cursor_idx)
+        } else {
+            util::next_grapheme_idx(&self.user_input, self.cursor_idx)
+        };
+        if let Some(next_idx) = next_idx {
+            self.move_cursor(next_idx);
+        }
+    }
+
+    /// HOME, or CTRL+A, moves the cursor to the start of the input.
+    fn home(&mut self) {
+        self.move_cursor(0);
+    }
+
+    /// END, or CTRL+E, moves the cursor to the end of the input.
+    fn end(&mut self) {
+        self.move_cursor(self.user_input.len());
+    }
+
+    /// CTRL+R searches the history backwards, replacing the input with the line accepted.
+    fn reverse_search(&mut self) -> std::io::Result<()> {
+        self.clear_suggestions();
         execute!(
             self.writer,
-            MoveLeft(num_of_chars_to_clear),
-            Clear(ClearType::UntilNewLine)
+            MoveInInput {
+                prompt_len: self.prompt_len,
+                from: input_width(&self.user_input[..self.cursor_idx]),
+                to: 0,
+            },
+            MoveToColumn(0),
+            Clear(ClearType::FromCursorDown)
         )
         .unwrap();
-        if self.suggestion_printed_below {
-            clear_line_below(&mut self.writer);
-            self.suggestion_printed_below = false;
+        if let Some(found) = reverse_search(&mut self.writer, &mut self.events, &self.history)? {
+            self.user_input = found;
+        }
+        print_prompt(&mut self.writer);
+        print_input_tail(&mut self.writer, self.prompt_len, &self.user_input, 0);
+        self.writer.flush().unwrap();
+        self.cursor_idx = self.user_input.len();
+        Ok(())
+    }
+
+    /// CTRL+U erases the input before the cursor.
+    fn kill_to_start(&mut self) {
+        if self.cursor_idx == 0 {
+            return;
         }
-        ControlFlow::Continue(())
+        let erased_end = self.cursor_idx;
+        self.move_cursor(0);
+        self.user_input.replace_range(..erased_end, "");
+        self.reprint_tail();
+        self.clear_suggestions();
+    }
+
+    /// CTRL+K erases the input from the cursor on.
+    fn kill_to_end(&mut self) {
+        if self.cursor_idx < self.user_input.len() {
+            self.user_input.truncate(self.cursor_idx);
+            execute!(self.writer, Clear(ClearType::FromCursorDown)).unwrap();
+            self.clear_suggestions();
+        }
+    }
+
+    /// Insert a character typed at the cursor.
+    fn insert(&mut self, c: char) {
+        self.user_input.insert(self.cursor_idx, c);
+        print_input_tail(
+            &mut self.writer,
+            self.prompt_len,
+            &self.user_input,
+            self.cursor_idx,
+        );
+        self.cursor_idx += c.len_utf8();
+        // shift back to just after the inserted character if it was put mid-line
+        execute!(
+            self.writer,
+            MoveInInput {
+                prompt_len: self.prompt_len,
+                from: input_width(&self.user_input),
+                to: input_width(&self.user_input[..self.cursor_idx]),
+            }
+        )
+        .unwrap();
     }
 }
 
-pub fn prompt2(cmd_schema: &clap::Command) -> std::io::Result<Vec<String>> {
-    Prompt::new().prompt()
+/// Interactive reverse incremental search through the history, just like bash's Ctrl+R.
+/// The search prompt is printed from the current cursor position, which should be
+/// at column 0 of a cleared row, and it's erased before returning.
+/// Typing refines the search, Ctrl+R jumps to the next older match,
+/// Enter accepts the match and Esc/Ctrl+G cancels the search.
+/// Returns the accepted history line or `None` if the search was cancelled.
+fn reverse_search(
+    writer: &mut impl Write,
+    events: &mut impl EventSource,
+    history: &HistoryHandle,
+) -> std::io::Result<Option<String>> {
+    let mut query = String::new();
+    let mut found_index: Option<usize> = None;
+    let mut printed_width = print_reverse_search(writer, &query, None, true);
+    loop {
+        let accepted = match events.read()? {
+            // ENTER
+            Event::Key(KeyEvent {
+                code: KeyCode::Enter,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(found_index.and_then(|idx| history.get_line(idx))),
+
+            // ESC | CTRL + G | CTRL + C
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc,
+                kind: KeyEventKind::Press,
+                ..
+            })
+            | Event::Key(KeyEvent {
+                code: KeyCode::Char('g' | 'c'),
+                kind: KeyEventKind::Press,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }) => Some(None),
+
+            // CTRL + R
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('r'),
+                kind: KeyEventKind::Press,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }) => {
+                if let Some(idx) = found_index {
+                    // keep the current match if there is no older one
+                    if let Some(older_idx) = history.search_back(&query, idx) {
+                        found_index = Some(older_idx);
+                    }
+                }
+                None
+            }
+
+            // BACKSPACE
+            Event::Key(KeyEvent {
+                code: KeyCode::Backspace,
+                kind: KeyEventKind::Press,
+                ..
+            }) => {
+                query.pop();
+                found_index = history.search_back(&query, usize::MAX);
+                None
+            }
+
+            // CHARACTERS
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                kind: KeyEventKind::Press,
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                ..
+            }) => {
+                query.push(c);
+                // the current match is still a candidate for the refined query
+                let from_index = found_index.map_or(usize::MAX, |idx| idx + 1);
+                found_index = history.search_back(&query, from_index);
+                None
+            }
+
+            _ => continue,
+        };
+
+        queue!(
+            writer,
+            MoveInInput {
+                prompt_len: 0,
+                from: printed_width,
+                to: 0,
+            },
+            Clear(ClearType::FromCursorDown)
+        )
+        .unwrap();
+        if let Some(accepted) = accepted {
+            writer.flush().unwrap();
+            return Ok(accepted);
+        }
+        let found = found_index.and_then(|idx| history.get_line(idx));
+        let failed = !query.is_empty() && found.is_none();
+        printed_width = print_reverse_search(writer, &query, found.as_deref(), !failed);
+    }
+}
+
+/// Print the reverse search prompt with the query and the matching history line.
+/// Returns the number of columns printed.
+fn print_reverse_search(
+    writer: &mut impl Write,
+    query: &str,
+    found: Option<&str>,
+    succeeded: bool,
+) -> usize {
+    let label = if succeeded {
+        "(reverse-i-search)"
+    } else {
+        "(failed reverse-i-search)"
+    };
+    let line = format!("{}`{}': {}", label, query, found.unwrap_or_default());
+    let term_width = terminal_width();
+    queue!(writer, Print(&line)).unwrap();
+    // force the cursor into the next row if the line ends exactly at the terminal width
+    if input_width(&line) % term_width == 0 {
+        queue!(writer, Print(" "), MoveLeft(1)).unwrap();
+    }
+    writer.flush().unwrap();
+    input_width(&line)
+}
+
+/// Outcome of completing the user input, see [`complete`].
+#[derive(Debug)]
+pub enum Completion<'a> {
+    /// Nothing to complete, the input is left as is
+    None,
+    /// A word matches no candidate, or more than one although the word is complete
+    Invalid(String),
+    /// The input is replaced by one with its words completed, with the cursor
+    /// at byte index `cursor`, right after the completed words
+    Replace { input: String, cursor: usize },
+    /// Candidates to complete the word starting at byte index `word_idx` of the input,
+    /// which runs until the cursor
+    Candidates {
+        completions: Completions<'a>,
+        candidates: Vec<String>,
+        word_idx: usize,
+    },
+}
+
+/// Complete the user input before the byte index `cursor`, like on a Tab press.
+/// Words are matched one by one down the command tree, against the subcommands
+/// or the values of the arguments they are given to. A word matched by a single
+/// candidate is completed, else the candidates of the first ambiguous word are returned,
+/// completing it first up to the prefix common to all candidates, like bash.
+/// After a complete last word, the candidates of the next word are returned.
+/// The input after the cursor is kept as is.
+/// Words are matched against the tries of the schema kept in `tries`, built as needed.
+pub fn complete<'a>(
+    cmd_schema: &'a clap::Command,
+    tries: &mut CompletionTries<'a>,
+    input: &str,
+    cursor: usize,
+) -> Completion<'a> {
+    let fuzzy_completion = CLI.lock().borrow().fuzzy_completion;
+    let (user_input, input_tail) = input.split_at(cursor);
+    if user_input.is_empty() {
+        return Completion::Candidates {
+            completions: Completions::Commands(cmd_schema),
+            candidates: util::get_visible_command_vector(cmd_schema),
+            word_idx: 0,
+        };
+    }
+    let replace = |completed: String, separator: &str| {
+        // a separator already after the cursor is not repeated, the cursor moves past it
+        let input = if !separator.is_empty() && input_tail.starts_with(separator) {
+            [completed.as_str(), input_tail].concat()
+        } else {
+            [completed.as_str(), separator, input_tail].concat()
+        };
+        Completion::Replace {
+            cursor: completed.len() + separator.len(),
+            input,
+        }
+    };
+
+    // words are split as on Enter, so quoted text is a single word
+    let words = match util::split_words(user_input) {
+        Ok(words) => words,
+        Err(err) => return Completion::Invalid(err),
+    };
+    let mut curr_cmd_schema = cmd_schema;
+    let mut new_user_input = user_input.to_string();
+    // values given to positional arguments of the current command
+    let mut positionals_given = 0;
+    // values given to positional arguments, left out of the next completions
+    let mut positional_values: Vec<String> = Vec::new();
+    // option given in the previous words, waiting for its values, with how many it took so far
+    let mut option_arg: Option<(&clap::Arg, usize)> = None;
+    for InputWord {
+        span,
+        value: word_input,
+    } in words
+    {
+        let word_idx = span.start;
+        // the words after an option are completed with the option values,
+        // an option taking several values takes the words until the next option
+        let pending_option = option_arg.take().filter(|(_, given)| {
+            *given == 0 || util::find_option(curr_cmd_schema, &word_input).is_none()
+        });
+        if pending_option.is_none() {
+            if let Some(option) = util::find_option(curr_cmd_schema, &word_input) {
+                if util::option_max_values(option) > 0 {
+                    option_arg = Some((option, 0));
+                }
+                continue;
+            }
+        }
+        if let Some((option, given)) = pending_option {
+            if given + 1 < util::option_max_values(option) {
+                option_arg = Some((option, given + 1));
+            }
+        }
+        let pending_option = pending_option.map(|(option, _)| option);
+        let cmd_arg =
+            pending_option.or_else(|| util::positional_arg(curr_cmd_schema, positionals_given));
+
+        let has_end_whitespace = user_input[span.end..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_whitespace());
+
+        // try to match input string against tree of commands or arguments
+        let cmd_trie = match cmd_arg {
+            Some(arg) => tries.arg_values_trie(arg),
+            None => tries.command_trie(curr_cmd_schema),
+        };
+
+        let mut cmd_matches = cmd_trie.collect_matches(&word_input);
+        if cmd_matches.is_empty() && fuzzy_completion {
+            let candidates = match cmd_arg {
+                Some(arg) => util::get_arg_values_vector(arg),
+                None => util::get_visible_command_vector(curr_cmd_schema),
+            };
+            cmd_matches = util::fuzzy_matches(&candidates, &word_input);
+        }
+        // a complete word equal to a candidate is not ambiguous with longer ones
+        if has_end_whitespace && cmd_matches.contains(&word_input) {
+            cmd_matches = vec![word_input.clone()];
+        }
+        // an argument without static possible values accepts any value given
+        if cmd_matches.is_empty() && cmd_arg.is_some_and(|arg| arg.get_possible_values().is_empty())
+        {
+            if pending_option.is_none() {
+                positionals_given += 1;
+                positional_values.push(word_input);
+            }
+            continue;
+        }
+        if cmd_matches.is_empty() || (cmd_matches.len() > 1 && has_end_whitespace) {
+            return Completion::Invalid(word_input);
+        }
+
+        // if more than one match then suggest command completion
+        if cmd_matches.len() > 1 {
+            // first complete up to the prefix common to all matches, like bash
+            let common_prefix = util::longest_common_prefix(&cmd_matches);
+            if common_prefix.starts_with(&word_input) && word_input.len() < common_prefix.len() {
+                // words before may have been completed, shifting this one
+                let word_end_idx = span.end + new_user_input.len() - user_input.len();
+                new_user_input.insert_str(word_end_idx, &common_prefix[word_input.len()..]);
+                return replace(new_user_input, "");
+            }
+            let completions = match cmd_arg {
+                Some(arg) => Completions::ArgValues(arg),
+                None => Completions::Commands(curr_cmd_schema),
+            };
+            return Completion::Candidates {
+                completions,
+                candidates: cmd_matches,
+                word_idx,
+            };
+        }
+
+        // else a full match is found
+        let cmd = cmd_matches.last().unwrap();
+        if word_input != *cmd {
+            // replace the whole word, a fuzzy match doesn't start with it
+            let word_start_idx = span.start + new_user_input.len() - user_input.len();
+            let word_end_idx = word_start_idx + span.len();
+            new_user_input.replace_range(word_start_idx..word_end_idx, cmd);
+        }
+
+        // command is final, process it now
+        if cmd_arg.is_some() {
+            if pending_option.is_none() {
+                positionals_given += 1;
+                positional_values.push(cmd.clone());
+            }
+        } else {
+            curr_cmd_schema = util::find_subcommand(curr_cmd_schema, cmd).unwrap();
+            positionals_given = 0;
+            positional_values.clear();
+        }
+    }
+
+    if user_input.ends_with(' ') {
+        let next_completions = if let Some((option, 0)) = option_arg {
+            Some((
+                Completions::ArgValues(option),
+                util::get_arg_values_vector(option),
+            ))
+        } else if curr_cmd_schema.get_subcommands().next().is_some() {
+            Some((
+                Completions::Commands(curr_cmd_schema),
+                util::get_visible_command_vector(curr_cmd_schema),
+            ))
+        } else {
+            util::positional_arg(curr_cmd_schema, positionals_given).map(|arg| {
+                (
+                    Completions::ArgValues(arg),
+                    util::remaining_arg_values(arg, &positional_values),
+                )
+            })
+        };
+        if let Some((completions, candidates)) = next_completions {
+            return Completion::Candidates {
+                completions,
+                candidates,
+                word_idx: user_input.len(),
+            };
+        }
+    }
+
+    if user_input != new_user_input {
+        return replace(new_user_input, " ");
+    }
+    Completion::None
+}
+
+/// Maximum number of completion candidates to list one per line with their description.
+const DETAILED_COMPLETIONS_MAX: usize = 6;
+
+/// The schema completion candidates come from, used to look up their descriptions.
+#[derive(Clone, Copy, Debug)]
+pub enum Completions<'a> {
+    /// Subcommands of a command
+    Commands(&'a clap::Command),
+    /// Possible values of an argument
+    ArgValues(&'a clap::Arg),
+}
+
+impl Completions<'_> {
+    /// Get the description of a candidate, if it has one.
+    fn about(&self, name: &str) -> Option<String> {
+        match self {
+            Completions::Commands(cmd_app) => cmd_app
+                .find_subcommand(name)?
+                .get_about()
+                .map(|about| about.to_string()),
+            Completions::ArgValues(arg) => util::get_arg_possible_values(arg)
+                .into_iter()
+                .find(|value| value.matches(name, false))?
+                .get_help()
+                .map(|help| help.to_string()),
+        }
+    }
+}
+
+/// Print out list of commands as for completion suggestions.
+/// Few candidates are listed one per line with their description, aligned like help,
+/// otherwise all are listed in a single line.
+/// Returns the number of rows printed, lines wider than the terminal take multiple rows.
+fn print_command_completions(
+    writer: &mut impl Write,
+    completions: Completions,
+    cmds: &[String],
+) -> u16 {
+    if cmds.is_empty() {
+        ring_bell(writer);
+    }
+    let term_width = terminal_width();
+    let abouts: Vec<Option<String>> = cmds.iter().map(|cmd| completions.about(cmd)).collect();
+    if cmds.len() > DETAILED_COMPLETIONS_MAX || abouts.iter().all(Option::is_none) {
+        let line: String = cmds.iter().map(|cmd| format!("{}  ", cmd)).collect();
+        queue!(writer, Print(&line)).unwrap();
+        return wrapped_rows(input_width(&line), term_width);
+    }
+    let mut rows = 0;
+    for (i, (cmd, about)) in cmds.iter().zip(&abouts).enumerate() {
+        if i > 0 {
+            queue!(writer, SmartNewLine(1)).unwrap();
+        }
+        let line = format!(" {:6}       {}", cmd, about.as_deref().unwrap_or_default());
+        queue!(writer, Print(&line)).unwrap();
+        rows += wrapped_rows(input_width(&line), term_width);
+    }
+    rows
+}
+
+/// Number of terminal rows taken by a line of `width` columns, at least one.
+fn wrapped_rows(width: usize, term_width: usize) -> u16 {
+    width.div_ceil(term_width).max(1) as u16
+}
+
+/// Replace the user input printed after the prompt with a new input.
+/// The cursor is moved from its current column back to the start of the input,
+/// everything from there on is cleared and the new input is printed.
+fn reprint_input(writer: &mut impl Write, prompt_len: u16, cursor_col: usize, new_input: &str) {
+    queue!(
+        writer,
+        MoveInInput {
+            prompt_len,
+            from: cursor_col,
+            to: 0,
+        },
+        Clear(ClearType::FromCursorDown)
+    )
+    .unwrap();
+    print_input_tail(writer, prompt_len, new_input, 0);
+    writer.flush().unwrap();
+}
+
+/// Clear all lines below the user input, where suggestions are printed,
+/// and return to the previous position.
+/// The input may wrap to rows below the cursor, those are kept.
+fn clear_below_input(
+    writer: &mut impl Write,
+    prompt_len: u16,
+    user_input: &str,
+    cursor_idx: usize,
+) {
+    execute!(
+        writer,
+        SavePosition,
+        MoveInInput {
+            prompt_len,
+            from: input_width(&user_input[..cursor_idx]),
+            to: input_width(user_input),
+        },
+        MoveDown(1),
+        MoveToColumn(0),
+        Clear(ClearType::FromCursorDown),
+        RestorePosition
+    )
+    .unwrap();
+}
+
+/// Print out message "Unknown command" with new line
+pub fn print_invalid_input(writer: &mut impl Write, input: &str) {
+    ring_bell(writer);
+    execute!(
+        writer,
+        PrintStyled(theme::failure("x")),
+        Print(" Invalid input: "),
+        Print(input),
+        SmartNewLine(1)
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{Error, ErrorKind};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use clap::{Arg, ArgAction, Command};
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use crossterm::tty::IsTty;
+
+    use crate::cli::{
+        complete, deinitialize, edit_in_editor, initialize, input_width, install_panic_hook,
+        is_interactive, no_color, read_text_from, terminal_width, wrapped_rows, Completion,
+        Completions, EventSource, Prompt, CLI, RAW_MODE_ENABLED, STATE_TEST_LOCK,
+    };
+    use crate::history::HistoryHandle;
+    use crate::util::CompletionTries;
+
+    /// Events fed to the prompt in order, failing when there are no more,
+    /// so a prompt still waiting for input ends.
+    struct SyntheticEvents(VecDeque<Event>);
+
+    impl EventSource for SyntheticEvents {
+        fn read(&mut self) -> std::io::Result<Event> {
+            self.0
+                .pop_front()
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "no more events"))
+        }
+
+        fn poll(&mut self, _timeout: Duration) -> std::io::Result<bool> {
+            Ok(!self.0.is_empty())
+        }
+    }
+
+    /// Key presses typing `text`, where `\t` is Tab, `\n` is Enter,
+    /// `\x08` is Backspace and `\x1b` is arrow up.
+    fn type_keys(text: &str) -> Vec<Event> {
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        text.chars()
+            .map(|c| match c {
+                '\t' => key(KeyCode::Tab),
+                '\n' => key(KeyCode::Enter),
+                '\x08' => key(KeyCode::Backspace),
+                '\x1b' => key(KeyCode::Up),
+                _ => key(KeyCode::Char(c)),
+            })
+            .collect()
+    }
+
+    /// Drive a prompt with the keys typing `text`, see [`type_keys`].
+    /// Returns the line of arguments entered and the output printed.
+    fn run_prompt(cmd_schema: &Command, text: &str) -> (std::io::Result<Vec<String>>, String) {
+        let events = SyntheticEvents(type_keys(text).into());
+        let mut output = Vec::new();
+        let _no_color = no_color();
+        let args = Prompt::new(cmd_schema, &mut output, events).read_line();
+        (args, String::from_utf8(output).unwrap())
+    }
+
+    /// Command tree like the one of the prompt, with static query terms to complete.
+    fn completion_schema() -> Command {
+        Command::new("gerrit").subcommand(
+            Command::new("change").subcommands([
+                Command::new("show").arg(Arg::new("ID").required(true)),
+                Command::new("submit").arg(Arg::new("ID").required(true)),
+                Command::new("review")
+                    .arg(Arg::new("ID").required(true))
+                    .arg(Arg::new("message").short('m').long("message").num_args(1..))
+                    .arg(
+                        Arg::new("yes")
+                            .short('y')
+                            .long("yes")
+                            .action(ArgAction::SetTrue),
+                    ),
+                Command::new("query").arg(Arg::new("QUERY").num_args(0..).value_parser([
+                    "is:open",
+                    "is:wip",
+                    "owner:self",
+                ])),
+            ]),
+        )
+    }
+
+    /// Complete the whole input, with the cursor at its end.
+    fn complete_input<'a>(cmd_schema: &'a Command, input: &str) -> Completion<'a> {
+        complete(
+            cmd_schema,
+            &mut CompletionTries::default(),
+            input,
+            input.len(),
+        )
+    }
+
+    #[test]
+    fn test_terminal_width() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        assert_eq!(terminal_width(), 80);
+        // in the shell the width is asked to the terminal, if there's one
+        CLI.lock().borrow_mut().interactive = true;
+        let width = terminal_width();
+        CLI.lock().borrow_mut().interactive = false;
+        assert!(width >= 1);
+    }
+
+    #[test]
+    fn test_prompt_enters_line() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let schema = completion_schema();
+        let (args, output) = run_prompt(&schema, "change show 12\n");
+        assert_eq!(args.unwrap(), vec!["change", "show", "12"]);
+        assert!(output.contains("change show 12"));
+        // no line is returned until Enter
+        let (args, _) = run_prompt(&schema, "change show 12");
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_prompt_backspace() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let schema = completion_schema();
+        let (args, _) = run_prompt(&schema, "change shx\x08ow 1\x082\n");
+        assert_eq!(args.unwrap(), vec!["change", "show", "2"]);
+    }
+
+    #[test]
+    fn test_prompt_completion() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let schema = completion_schema();
+        let (args, output) = run_prompt(&schema, "ch\tsu\t7\n");
+        assert_eq!(args.unwrap(), vec!["change", "submit", "7"]);
+        assert!(output.contains("change submit 7"));
+        // abbreviated commands are completed on Enter too
+        let (args, _) = run_prompt(&schema, "ch sh 7\n");
+        assert_eq!(args.unwrap(), vec!["change", "show", "7"]);
+    }
+
+    #[test]
+    fn test_prompt_options() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let schema = completion_schema();
+        let (args, _) = run_prompt(&schema, "change review 12 -y\n");
+        assert_eq!(args.unwrap(), vec!["change", "review", "12", "-y"]);
+        let (args, _) = run_prompt(&schema, "change review 12 -m lgtm\n");
+        assert_eq!(args.unwrap(), vec!["change", "review", "12", "-m", "lgtm"]);
+        // an option taking several values takes the words until the next option
+        let (args, _) = run_prompt(&schema, "change review 12 --message looks good -y\n");
+        assert_eq!(
+            args.unwrap(),
+            vec!["change", "review", "12", "--message", "looks", "good", "-y"]
+        );
+    }
+
+    #[test]
+    fn test_prompt_history() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        HistoryHandle::get().clear();
+        let schema = completion_schema();
+        run_prompt(&schema, "change show 1\n").0.unwrap();
+        let (args, _) = run_prompt(&schema, "\x1b\n");
+        assert_eq!(args.unwrap(), vec!["change", "show", "1"]);
+        HistoryHandle::get().clear();
+    }
+
+    #[test]
+    fn test_read_text() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let read = |text: &str| {
+            let mut events = SyntheticEvents(type_keys(text).into());
+            read_text_from(&mut Vec::new(), &mut events)
+        };
+        let text = read("Looks good,\n\nbut fixx\x08 the typo\n.\n").unwrap();
+        assert_eq!(text.as_deref(), Some("Looks good,\n\nbut fix the typo"));
+        // a dot within a line doesn't end the text
+        let text = read("a.\n .\n.\n").unwrap();
+        assert_eq!(text.as_deref(), Some("a.\n ."));
+        let mut events = SyntheticEvents(type_keys("one\ntwo").into());
+        events.0.push_back(Event::Key(KeyEvent::new(
+            KeyCode::Char('d'),
+            KeyModifiers::CONTROL,
+        )));
+        let text = read_text_from(&mut Vec::new(), &mut events).unwrap();
+        assert_eq!(text.as_deref(), Some("one\ntwo"));
+        let mut events = SyntheticEvents(type_keys("draft\n").into());
+        events.0.push_back(Event::Key(KeyEvent::new(
+            KeyCode::Char('c'),
+            KeyModifiers::CONTROL,
+        )));
+        assert_eq!(read_text_from(&mut Vec::new(), &mut events).unwrap(), None);
+        // without an end the text is never given
+        assert!(read("unfinished\n").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_in_editor() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let visual = std::env::var_os("VISUAL");
+        std::env::remove_var("VISUAL");
+        let editor = std::env::var_os("EDITOR");
+        let temp_files = || {
+            std::fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    let prefix = format!("gerrit-edit-{}-", std::process::id());
+                    name.to_string_lossy().starts_with(&prefix)
+                })
+                .count()
+        };
+        // the editor appends a line to the file, given as its last argument
+        std::env::set_var("EDITOR", "sh -c 'echo edited >> \"$0\"'");
+        assert_eq!(edit_in_editor("initial\n").unwrap(), "initial\nedited\n");
+        std::env::set_var("EDITOR", "false");
+        assert!(edit_in_editor("initial").is_err());
+        std::env::set_var("EDITOR", "sh -c 'unbalanced");
+        assert!(edit_in_editor("initial").is_err());
+        assert_eq!(temp_files(), 0);
+        match editor {
+            Some(editor) => std::env::set_var("EDITOR", editor),
+            None => std::env::remove_var("EDITOR"),
+        }
+        if let Some(visual) = visual {
+            std::env::set_var("VISUAL", visual);
+        }
+    }
+
+    #[test]
+    fn test_initialize_without_terminal() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        // only checked where the tests run without a terminal, like in CI
+        if std::io::stdin().is_tty() && std::io::stdout().is_tty() {
+            return;
+        }
+        let err = initialize().err().unwrap();
+        assert!(err.to_string().contains("is not a terminal"));
+        assert!(!is_interactive());
+        assert!(!RAW_MODE_ENABLED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_panic_hook_restores_terminal() {
+        install_panic_hook();
+        RAW_MODE_ENABLED.store(true, Ordering::SeqCst);
+        let result = std::thread::spawn(|| panic!("forced panic")).join();
+        assert!(result.is_err());
+        assert!(!RAW_MODE_ENABLED.load(Ordering::SeqCst));
+        assert!(!crossterm::terminal::is_raw_mode_enabled().unwrap());
+        // restoring again after the hook is a no-op
+        deinitialize();
+        assert!(!RAW_MODE_ENABLED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_input_width_mixed() {
+        assert_eq!(input_width("change show"), 11);
+        assert_eq!(input_width("日本語"), 6);
+        assert_eq!(input_width("a👍b"), 4);
+        assert_eq!(input_width("cafe\u{301}"), 4);
+    }
+
+    #[test]
+    fn test_wrapped_rows() {
+        assert_eq!(wrapped_rows(0, 80), 1);
+        assert_eq!(wrapped_rows(79, 80), 1);
+        assert_eq!(wrapped_rows(80, 80), 1);
+        assert_eq!(wrapped_rows(81, 80), 2);
+        assert_eq!(wrapped_rows(240, 80), 3);
+    }
+
+    #[test]
+    fn test_complete_empty_input() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let Completion::Candidates {
+            completions: Completions::Commands(cmd),
+            candidates,
+            word_idx,
+        } = complete_input(&root, "")
+        else {
+            panic!("expected the root commands");
+        };
+        assert_eq!(cmd.get_name(), "gerrit");
+        assert_eq!(candidates, vec!["change"]);
+        assert_eq!(word_idx, 0);
+    }
+
+    #[test]
+    fn test_complete_single_match() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let Completion::Replace { input, cursor } = complete_input(&root, "ch sh") else {
+            panic!("expected the input completed");
+        };
+        assert_eq!(input, "change show ");
+        assert_eq!(cursor, input.len());
+        assert!(matches!(
+            complete_input(&root, "change show 123"),
+            Completion::None
+        ));
+    }
+
+    #[test]
+    fn test_complete_ambiguous() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let Completion::Candidates {
+            candidates,
+            word_idx,
+            ..
+        } = complete_input(&root, "change s")
+        else {
+            panic!("expected candidates");
+        };
+        assert_eq!(candidates, vec!["show", "submit"]);
+        assert_eq!(word_idx, "change ".len());
+        // the common prefix is completed first
+        let Completion::Replace { input, cursor } = complete_input(&root, "change query i") else {
+            panic!("expected the common prefix completed");
+        };
+        assert_eq!(input, "change query is:");
+        assert_eq!(cursor, input.len());
+    }
+
+    #[test]
+    fn test_complete_next_query_term() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let Completion::Candidates {
+            completions: Completions::ArgValues(arg),
+            candidates,
+            word_idx,
+        } = complete_input(&root, "change query is:open ")
+        else {
+            panic!("expected the query terms");
+        };
+        assert_eq!(arg.get_id(), "QUERY");
+        assert_eq!(candidates, vec!["is:wip", "owner:self"]);
+        assert_eq!(word_idx, "change query is:open ".len());
+    }
+
+    #[test]
+    fn test_complete_invalid_word() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        assert!(matches!(
+            complete_input(&root, "change nope"),
+            Completion::Invalid(word) if word == "nope"
+        ));
+    }
+
+    #[test]
+    fn test_complete_quoted_words() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let Completion::Candidates { candidates, .. } =
+            complete_input(&root, "change query \"is:open\" ")
+        else {
+            panic!("expected the query terms");
+        };
+        assert_eq!(candidates, vec!["is:wip", "owner:self"]);
+        let Completion::Replace { input, .. } = complete_input(&root, "\"change\" sh") else {
+            panic!("expected the input completed");
+        };
+        assert_eq!(input, "\"change\" show ");
+        assert!(matches!(
+            complete_input(&root, "change \"sh"),
+            Completion::Invalid(err) if err == "missing closing quote \""
+        ));
+    }
+
+    #[test]
+    fn test_complete_options() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        assert!(matches!(
+            complete_input(&root, "change review 12 -m looks good "),
+            Completion::None
+        ));
+        assert!(matches!(
+            complete_input(&root, "change review 12 --message looks -y "),
+            Completion::None
+        ));
+    }
+
+    #[test]
+    fn test_complete_keeps_input_after_cursor() {
+        let _state_guard = STATE_TEST_LOCK.lock();
+        let root = completion_schema();
+        let input = "change sh| 123";
+        let cursor = input.find('|').unwrap();
+        let input = input.replace('|', "");
+        let Completion::Replace { input, cursor } =
+            complete(&root, &mut CompletionTries::default(), &input, cursor)
+        else {
+            panic!("expected the word before the cursor completed");
+        };
+        assert_eq!(input, "change show 123");
+        assert_eq!(cursor, "change show ".len());
+    }
 }
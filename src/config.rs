@@ -0,0 +1,476 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::history::HistoryDedup;
+use crate::keymap::{Action, Keymap};
+use crate::theme::{Theme, THEME_NAMES};
+use crate::util::{CmdError, SpinnerStyle};
+
+/// The program configuration is persisted in a TOML file in the user config directory.
+/// Right now, it's loaded once at program start and saved back whenever a command
+/// changes it, like `remote add`.
+/// The default remote settings at the top of the file are overridden by environment variables,
+/// so secrets don't have to be stored in the file, see [`Config::connection_settings`].
+///
+/// Example:
+/// ```toml
+/// url = "https://gerrit.company.com"
+/// user = "john"
+/// http_password_file = "~/.gerrit_pw"
+/// ssl_verify = true
+/// default_query = "owner:self is:open"
+/// query_on_enter = true
+/// fuzzy_completion = true
+/// spinner = "braille"
+/// spinner_delay_ms = 500
+/// timeout_secs = 30
+/// retries = 2
+/// restore_modes = true
+/// bell = true
+/// confirm_quit = true
+/// history_dedup = "global"
+/// history_size = 1000
+/// history_ignore_space = true
+/// history_per_mode = true
+/// theme = "mono"
+///
+/// [queries]
+/// mine = "owner:self is:open"
+///
+/// [status_colors]
+/// NEW = "blue"
+/// ABANDONED = "dark_grey"
+///
+/// [theme_colors]
+/// symbol = "cyan"
+/// change_number = "magenta"
+///
+/// [keys]
+/// ctrl-p = "history-up"
+/// ctrl-n = "history-down"
+///
+/// [remotes.work]
+/// url = "https://gerrit.work.com"
+/// user = "john"
+/// ```
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+    /// URL of the default remote, overridden by `GERRIT_URL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// User of the default remote, overridden by `GERRIT_USER`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// HTTP password of the default remote, overridden by `GERRIT_PW`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_password: Option<String>,
+    /// File containing the HTTP password, used when `http_password` is not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_password_file: Option<PathBuf>,
+    /// Verify SSL certificates of the remote, overridden by `GERRIT_SSL_VERIFY`.
+    /// Verification is enabled by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_verify: Option<bool>,
+    /// Seconds to wait for a response to a request, overridden by `GERRIT_TIMEOUT`.
+    /// It's 30 seconds by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Times to retry a request that timed out or failed with a server error,
+    /// overridden by `GERRIT_RETRIES`. Requests are not retried by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Query used by `change query` when no query is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_query: Option<String>,
+    /// Run the default query when entering the change mode
+    #[serde(default)]
+    pub query_on_enter: bool,
+    /// Name of the remote in use, instead of the default remote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Show the name of the remote in use in the prompt, like `work/change>`.
+    /// It's shown by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_remote: Option<bool>,
+    /// Complete with fuzzy matches, like `shw` to `show`, when none starts with the input
+    #[serde(default)]
+    pub fuzzy_completion: bool,
+    /// Style of the loading animation: dots, braille or bar
+    #[serde(default)]
+    pub spinner: SpinnerStyle,
+    /// Milliseconds a command takes before the loading animation starts, 1000 by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spinner_delay_ms: Option<u64>,
+    /// Ring the terminal bell on invalid input and on completion without candidates
+    #[serde(default)]
+    pub bell: bool,
+    /// Ask for confirmation before CTRL+D quits the program from the root mode
+    #[serde(default)]
+    pub confirm_quit: bool,
+    /// How duplicated lines are kept out of the command history:
+    /// none, consecutive or global. Consecutive duplicates are dropped by default.
+    #[serde(default)]
+    pub history_dedup: HistoryDedup,
+    /// Maximum number of lines kept in the command history, unlimited by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_size: Option<usize>,
+    /// Keep lines starting with a space out of the command history
+    #[serde(default)]
+    pub history_ignore_space: bool,
+    /// Scroll through the lines entered in the current mode only, when there are any
+    #[serde(default)]
+    pub history_per_mode: bool,
+    /// Enter again the modes the program was in when it quit last time
+    #[serde(default)]
+    pub restore_modes: bool,
+    /// Modes the program was in when it quit last time, as the args of each mode,
+    /// saved only if `restore_modes` is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modes: Vec<Vec<String>>,
+    /// Colors of change statuses replacing the default ones, indexed by status
+    #[serde(default)]
+    pub status_colors: BTreeMap<String, String>,
+    /// Name of the built-in color theme: default or mono
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Colors replacing the ones of the theme, indexed by the piece of output
+    /// as named in [`Theme`], like symbol, change_number or status_new
+    #[serde(default)]
+    pub theme_colors: BTreeMap<String, String>,
+    /// Keys bound to editing actions of the prompt, replacing their default bindings,
+    /// like `ctrl-p = "history-up"`
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    /// Queries saved with `change query --save`, indexed by name
+    #[serde(default)]
+    pub queries: BTreeMap<String, String>,
+    /// Remotes available to connect to, indexed by name
+    #[serde(default)]
+    pub remotes: BTreeMap<String, RemoteConfig>,
+}
+
+/// A named Gerrit server to connect to.
+/// The HTTP password is taken from the environment, see [`Config::remote_settings`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub user: String,
+}
+
+/// Settings to connect to a Gerrit server,
+/// resolved from the configuration file and environment variables.
+#[derive(Clone)]
+pub struct ConnectionSettings {
+    pub url: String,
+    pub user: String,
+    pub http_password: String,
+    pub ssl_verify: bool,
+}
+
+impl Config {
+    /// Path of the configuration file: `<user config dir>/gerrit/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gerrit").join("config.toml"))
+    }
+
+    /// Load the configuration from file.
+    /// A missing file is not an error, the default configuration is returned instead.
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        toml::from_str(&content).map_err(|err| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err.message()),
+            )
+        })
+    }
+
+    /// Save the configuration to file, creating its directory if needed.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no user config directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = toml::to_string_pretty(self)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        std::fs::write(path, content)
+    }
+
+    /// Resolve the settings to connect to the remote in use,
+    /// or to the default remote if none is in use.
+    /// Returns a printable error message naming the missing setting otherwise.
+    pub fn connection_settings(&self) -> Result<ConnectionSettings, String> {
+        match &self.remote {
+            Some(name) => self.remote_settings(name),
+            None => self.default_settings(),
+        }
+    }
+
+    /// Resolve the settings to connect to the default remote.
+    /// Environment variables take precedence over the values in the configuration file.
+    fn default_settings(&self) -> Result<ConnectionSettings, String> {
+        let url = env_or("GERRIT_URL", self.url.as_deref())
+            .ok_or_else(|| missing_setting("url", "GERRIT_URL"))?;
+        let user = env_or("GERRIT_USER", self.user.as_deref())
+            .ok_or_else(|| missing_setting("user", "GERRIT_USER"))?;
+        let http_password = match env_or("GERRIT_PW", self.http_password.as_deref()) {
+            Some(http_password) => http_password,
+            None => self.read_http_password_file()?,
+        };
+        Ok(ConnectionSettings {
+            url,
+            user,
+            http_password,
+            ssl_verify: self.ssl_verify()?,
+        })
+    }
+
+    /// Resolve the settings to connect to the remote named `name`.
+    /// The HTTP password is taken from `GERRIT_PW_<NAME>`, with the name in upper case,
+    /// then from the default remote password.
+    pub fn remote_settings(&self, name: &str) -> Result<ConnectionSettings, String> {
+        let remote = self
+            .remotes
+            .get(name)
+            .ok_or_else(|| format!("no such remote '{}'", name))?;
+        let remote_var = format!("GERRIT_PW_{}", name.to_uppercase().replace('-', "_"));
+        let http_password = match std::env::var(remote_var).ok() {
+            Some(http_password) => http_password,
+            None => match env_or("GERRIT_PW", self.http_password.as_deref()) {
+                Some(http_password) => http_password,
+                None => self.read_http_password_file()?,
+            },
+        };
+        Ok(ConnectionSettings {
+            url: remote.url.clone(),
+            user: remote.user.clone(),
+            http_password,
+            ssl_verify: self.ssl_verify()?,
+        })
+    }
+
+    /// Parse the colors of change statuses given in `status_colors`,
+    /// indexed by the status in uppercase as it's displayed.
+    pub fn status_colors(&self) -> Result<HashMap<String, Color>, String> {
+        self.status_colors
+            .iter()
+            .map(|(status, color)| {
+                let color = Color::try_from(color.as_str())
+                    .map_err(|_| format!("invalid color '{}' for status {}", color, status))?;
+                Ok((status.to_uppercase(), color))
+            })
+            .collect()
+    }
+
+    /// Get the color theme named in `theme`, the default one if none,
+    /// with the colors replaced by the ones given in `theme_colors`.
+    pub fn theme(&self) -> Result<Theme, String> {
+        let name = self.theme.as_deref().unwrap_or("default");
+        let mut theme = Theme::named(name).ok_or_else(|| {
+            format!(
+                "unknown theme '{}', expected one of: {}",
+                name,
+                THEME_NAMES.join(", ")
+            )
+        })?;
+        for (piece, color) in &self.theme_colors {
+            let color = Color::try_from(color.as_str())
+                .map_err(|_| format!("invalid color '{}' for {}", color, piece))?;
+            theme.set_color(piece, color)?;
+        }
+        Ok(theme)
+    }
+
+    /// Get the default emacs-like keymap with the bindings given in `keys` on top.
+    pub fn keymap(&self) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+        for (key, action_name) in &self.keys {
+            let action = Action::named(action_name)
+                .ok_or_else(|| format!("unknown action '{}' for key {}", action_name, key))?;
+            keymap.bind(key, action)?;
+        }
+        Ok(keymap)
+    }
+
+    /// Get the timeout of requests, from `GERRIT_TIMEOUT` or the configuration.
+    pub fn request_timeout(&self) -> Result<Duration, String> {
+        let secs = match std::env::var("GERRIT_TIMEOUT") {
+            Ok(value) => u64::from_str(&value)
+                .map_err(|_| format!("invalid GERRIT_TIMEOUT value '{}'", value))?,
+            Err(_) => self.timeout_secs.unwrap_or(30),
+        };
+        Ok(Duration::from_secs(secs))
+    }
+
+    /// Get the number of retries of failed requests, from `GERRIT_RETRIES` or the configuration.
+    pub fn request_retries(&self) -> Result<u32, String> {
+        match std::env::var("GERRIT_RETRIES") {
+            Ok(value) => u32::from_str(&value)
+                .map_err(|_| format!("invalid GERRIT_RETRIES value '{}'", value)),
+            Err(_) => Ok(self.retries.unwrap_or(0)),
+        }
+    }
+
+    /// Get whether to verify SSL certificates, from `GERRIT_SSL_VERIFY` or the configuration.
+    fn ssl_verify(&self) -> Result<bool, String> {
+        match std::env::var("GERRIT_SSL_VERIFY") {
+            Ok(value) => parse_bool(&value)
+                .ok_or_else(|| format!("invalid GERRIT_SSL_VERIFY value '{}'", value)),
+            Err(_) => Ok(self.ssl_verify.unwrap_or(true)),
+        }
+    }
+
+    /// Read the HTTP password from the file given in `http_password_file`.
+    fn read_http_password_file(&self) -> Result<String, String> {
+        let Some(path) = &self.http_password_file else {
+            return Err(missing_setting("http_password", "GERRIT_PW"));
+        };
+        let path = expand_home(path);
+        std::fs::read_to_string(&path)
+            .map(|content| content.trim().to_string())
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))
+    }
+}
+
+/// Load the configuration, failing the command if it can't be loaded.
+pub fn load_config() -> Result<Config, CmdError> {
+    Config::load().map_err(|err| CmdError::Failed(format!("Failed to load config: {}", err)))
+}
+
+/// Save the configuration, failing the command if it can't be saved.
+pub fn save_config(config: &Config) -> Result<(), CmdError> {
+    config
+        .save()
+        .map_err(|err| CmdError::Failed(format!("Failed to save config: {}", err)))
+}
+
+/// Message for a missing setting that was expected in the config file or an env var.
+fn missing_setting(key: &str, env_var: &str) -> String {
+    let path = Config::path().map_or("config file".to_string(), |p| p.display().to_string());
+    format!("missing {}: set {} or `{}` in {}", key, env_var, key, path)
+}
+
+/// Get the value of the environment variable `var`, or else a copy of `value`.
+fn env_or(var: &str, value: Option<&str>) -> Option<String> {
+    std::env::var(var).ok().or_else(|| value.map(String::from))
+}
+
+/// Parse boolean values commonly given in environment variables.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Expand a leading `~` in a path to the user home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use crossterm::style::Color;
+
+    use crate::config::{parse_bool, Config};
+    use crate::keymap::Action;
+    use crate::util::SpinnerStyle;
+
+    #[test]
+    fn test_parse_bool() {
+        assert_eq!(parse_bool("TRUE"), Some(true));
+        assert_eq!(parse_bool("0"), Some(false));
+        assert_eq!(parse_bool("maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            url = "https://gerrit.company.com"
+            ssl_verify = true
+            spinner = "bar"
+            restore_modes = true
+            modes = [["change"]]
+
+            [remotes.work]
+            url = "https://gerrit.work.com"
+            user = "john"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.url.as_deref(), Some("https://gerrit.company.com"));
+        assert_eq!(config.ssl_verify, Some(true));
+        assert_eq!(config.spinner, SpinnerStyle::Bar);
+        assert!(config.restore_modes);
+        assert_eq!(config.modes, vec![vec!["change".to_string()]]);
+        assert_eq!(config.remotes["work"].user, "john");
+        assert!(config.remote.is_none());
+        // the default query is not run on entering the change mode unless enabled
+        assert!(!config.query_on_enter);
+        assert!(!config.confirm_quit);
+    }
+
+    #[test]
+    fn test_theme_config() {
+        let config: Config = toml::from_str(
+            r#"
+            theme = "mono"
+
+            [theme_colors]
+            symbol = "cyan"
+            "#,
+        )
+        .unwrap();
+        let theme = config.theme().unwrap();
+        assert_eq!(theme.symbol.foreground_color, Some(Color::Cyan));
+        assert_eq!(theme.change_number.foreground_color, None);
+        assert!(Config::default().theme().is_ok());
+        let config: Config = toml::from_str(r#"theme = "neon""#).unwrap();
+        assert!(config.theme().unwrap_err().contains("unknown theme 'neon'"));
+    }
+
+    #[test]
+    fn test_keys_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [keys]
+            ctrl-p = "history-up"
+            "#,
+        )
+        .unwrap();
+        let keymap = config.keymap().unwrap();
+        let ctrl_p = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.action(&ctrl_p), Some(Action::HistoryUp));
+        let config: Config = toml::from_str("keys = { ctrl-p = \"fly\" }").unwrap();
+        assert!(config
+            .keymap()
+            .unwrap_err()
+            .contains("unknown action 'fly'"));
+        let config: Config = toml::from_str("keys = { hyper-p = \"home\" }").unwrap();
+        assert!(config
+            .keymap()
+            .unwrap_err()
+            .contains("invalid key 'hyper-p'"));
+    }
+}
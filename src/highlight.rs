@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use crossterm::style::{Color, StyledContent, Stylize};
+use once_cell::sync::Lazy;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+/// Syntaxes of the languages known to the highlighter, loaded on first use.
+/// Lines are highlighted without their newline, as they're printed with `SmartNewLine`.
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_nonewlines);
+
+/// Color themes of the highlighter, loaded on first use.
+static THEME_SET: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
+
+/// Theme of the highlighted text, suited to dark terminals.
+const THEME: &str = "base16-ocean.dark";
+
+/// Files with more lines than this are not highlighted, it would be too slow.
+const MAX_HIGHLIGHT_LINES: usize = 5000;
+
+/// Syntax highlighter of the lines of a file, like the ones shown in a diff.
+/// The lines must be given in order, as the highlighting of a line depends on the ones before.
+pub struct Highlighter {
+    lines: HighlightLines<'static>,
+}
+
+impl Highlighter {
+    /// Get a highlighter for the language of the file at `path`, by its extension.
+    /// Returns none if the language is unknown or the file has more lines than
+    /// [`MAX_HIGHLIGHT_LINES`], then it should be printed out plain.
+    pub fn for_file(path: &str, line_count: usize) -> Option<Self> {
+        if line_count > MAX_HIGHLIGHT_LINES {
+            return None;
+        }
+        let extension = Path::new(path).extension()?.to_str()?;
+        let syntax = SYNTAX_SET.find_syntax_by_extension(extension)?;
+        let theme = THEME_SET.themes.get(THEME)?;
+        Some(Self {
+            lines: HighlightLines::new(syntax, theme),
+        })
+    }
+
+    /// Highlight the next line of the file into pieces colored by syntax.
+    /// The line is returned as a single plain piece if it can't be parsed.
+    pub fn highlight(&mut self, line: &str) -> Vec<StyledContent<String>> {
+        match self.lines.highlight_line(line, &SYNTAX_SET) {
+            Ok(ranges) => ranges
+                .into_iter()
+                .map(|(style, text)| {
+                    let fg = style.foreground;
+                    text.to_string().with(Color::Rgb {
+                        r: fg.r,
+                        g: fg.g,
+                        b: fg.b,
+                    })
+                })
+                .collect(),
+            Err(_) => vec![line.to_string().stylize()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::{Highlighter, MAX_HIGHLIGHT_LINES};
+
+    #[test]
+    fn test_highlight_known_language() {
+        let mut highlighter = Highlighter::for_file("src/main.rs", 10).unwrap();
+        let line = "fn main() { let x = 1; }";
+        let pieces = highlighter.highlight(line);
+        assert!(pieces.len() > 1);
+        let text: String = pieces
+            .iter()
+            .map(|piece| piece.content().as_str())
+            .collect();
+        assert_eq!(text, line);
+    }
+
+    #[test]
+    fn test_highlight_falls_back() {
+        assert!(Highlighter::for_file("notes.unknown-ext", 10).is_none());
+        assert!(Highlighter::for_file("Makefile-without-ext", 10).is_none());
+        assert!(Highlighter::for_file("src/main.rs", MAX_HIGHLIGHT_LINES + 1).is_none());
+    }
+}
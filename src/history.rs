@@ -1,6 +1,15 @@
 use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use clap::{Arg, ArgAction, Command};
+use crossterm::execute;
+use crossterm::style::Print;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+use crate::util::{CmdAction, CmdError};
+use crate::{cli, cliprintln, command_help, print_help};
 
 /// The command-line history is composed by a global history.
 /// Right now, history is reset every time the program is invoked,
@@ -13,59 +22,502 @@ use once_cell::sync::Lazy;
 /// Because of that, all code manipulating `HISTORY` needs to acquire RW lock
 /// in order to safely access the inner data. Hence `HISTORY` is thread safe.
 /// Thus use `HistoryHandle` as wrapper for safe code and to provide utility functions.
-static HISTORY: Lazy<RwLock<Vec<String>>> = Lazy::new(|| RwLock::default());
+/// Each line is kept with the time and the mode it was entered in.
+static HISTORY: Lazy<RwLock<Vec<HistoryEntry>>> = Lazy::new(|| RwLock::default());
+
+/// A line of `HISTORY`.
+#[derive(Clone, Debug, PartialEq)]
+struct HistoryEntry {
+    /// Time the line was entered
+    time: SystemTime,
+    /// Path of the modes the line was entered in, empty at the root
+    mode: Vec<String>,
+    line: String,
+}
+
+/// Policy applied to lines added to `HISTORY`, set from configuration.
+static HISTORY_POLICY: RwLock<HistoryPolicy> = RwLock::new(HistoryPolicy {
+    dedup: HistoryDedup::Consecutive,
+    max_size: None,
+    ignore_space: false,
+    per_mode: false,
+});
+
+/// Policy of what is kept in `HISTORY` when lines are added.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HistoryPolicy {
+    /// How duplicated lines are dropped
+    pub dedup: HistoryDedup,
+    /// Maximum number of lines kept, the oldest lines are dropped first
+    pub max_size: Option<usize>,
+    /// Lines starting with a space are not added, like bash's `HISTCONTROL=ignorespace`
+    pub ignore_space: bool,
+    /// Scrolling in a mode goes through the lines entered in that mode only,
+    /// or through all lines if none was entered in it yet
+    pub per_mode: bool,
+}
+
+impl HistoryPolicy {
+    /// Check if a line entered is kept out of the history, before it's trimmed.
+    pub fn ignores(&self, line: &str) -> bool {
+        self.ignore_space && line.starts_with(' ')
+    }
+}
+
+/// How a line added to `HISTORY` that is already there is handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryDedup {
+    /// Every line is kept, even repeated ones
+    None,
+    /// A line equal to the last one is not added again
+    #[default]
+    Consecutive,
+    /// All previous occurrences of the line are removed, keeping only the newest
+    Global,
+}
+
+/// Set the policy applied to lines added to `HISTORY` from now on.
+pub fn set_policy(policy: HistoryPolicy) {
+    *HISTORY_POLICY.write().unwrap() = policy;
+}
+
+/// Check if a line entered is kept out of `HISTORY` by the policy set.
+pub fn is_ignored(line: &str) -> bool {
+    HISTORY_POLICY.read().unwrap().ignores(line)
+}
+
+/// Add an entry to the history lines following a policy:
+/// duplicates, the same line in the same mode, are dropped according to `policy.dedup`
+/// and the oldest lines are dropped to stay within `policy.max_size`.
+/// Returns whether the line was added.
+fn push_line(lines: &mut Vec<HistoryEntry>, entry: HistoryEntry, policy: HistoryPolicy) -> bool {
+    let is_duplicate = |other: &HistoryEntry| other.line == entry.line && other.mode == entry.mode;
+    match policy.dedup {
+        HistoryDedup::None => {}
+        HistoryDedup::Consecutive => {
+            if lines.last().is_some_and(is_duplicate) {
+                return false;
+            }
+        }
+        HistoryDedup::Global => lines.retain(|other| !is_duplicate(other)),
+    }
+    lines.push(entry);
+    if let Some(max_size) = policy.max_size {
+        let excess = lines.len().saturating_sub(max_size);
+        lines.drain(..excess);
+    }
+    true
+}
+
+/// Get the indexes of the history lines to scroll through in a mode.
+/// With history per mode, those are the lines entered in the mode or in any of its submodes,
+/// falling back to all lines if there are none.
+fn mode_view(lines: &[HistoryEntry], mode: &[String], per_mode: bool) -> Vec<usize> {
+    if per_mode {
+        let scoped: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.mode.starts_with(mode))
+            .map(|(i, _)| i)
+            .collect();
+        if !scoped.is_empty() {
+            return scoped;
+        }
+    }
+    (0..lines.len()).collect()
+}
 
 /// `HistoryHandle` will scroll through the history lines and update `HISTORY`.
 /// Thus an index is kept to know where up in the history we have scrolled through.
 /// User of the HistoryHandle can `add` new lines to the history and scroll through the history
 /// using `up_next` and `down_next` to get previous and latest lines.
+/// Like in bash, scrolling down past the newest line gets back the line
+/// that was being typed when scrolling up started.
+/// The lines scrolled through are those of the current mode if history is per mode.
 pub struct HistoryHandle {
+    /// position of the line scrolled to in the view of the mode, see [`mode_view`],
+    /// the length of the view when not scrolling
     curr_index: usize,
+    /// line being typed when scrolling up started
+    draft: Option<String>,
+    /// path of the modes the prompt is in, where lines are added
+    mode: Vec<String>,
 }
 
 impl HistoryHandle {
-    /// Get a new `HistoryHandle` to manipulate `HISTORY`.
+    /// Get a new `HistoryHandle` to manipulate `HISTORY` from the current mode.
     pub fn get() -> Self {
-        let history = HISTORY.read().unwrap();
-        Self {
-            curr_index: history.len(),
-        }
+        let mut handle = Self {
+            curr_index: 0,
+            draft: None,
+            mode: cli::get_modes(),
+        };
+        handle.curr_index = handle.view(&HISTORY.read().unwrap()).len();
+        handle
+    }
+
+    /// Get the indexes in `HISTORY` of the lines scrolled through from the mode of the handle.
+    fn view(&self, history: &[HistoryEntry]) -> Vec<usize> {
+        let per_mode = HISTORY_POLICY.read().unwrap().per_mode;
+        mode_view(history, &self.mode, per_mode)
     }
 
     /// Add new line to the `HISTORY`.
-    /// This is a smart add because history will not duplicate
-    /// the last prompt line if it's added multiple times.
-    /// This will reset current index to last line in history.
+    /// Duplicated lines and the size of the history are handled by the policy set,
+    /// by default the last prompt line is not duplicated if it's added multiple times.
+    /// This will reset the scroll, even if the line is not added, so that
+    /// scrolling starts again from the newest line.
     pub fn add(&mut self, new_line: String) {
+        let policy = *HISTORY_POLICY.read().unwrap();
         let mut history = HISTORY.write().unwrap();
-        if let Some(last_line) = history.last() {
-            if &new_line == last_line {
-                return;
-            }
-        }
-        history.push(new_line);
-        self.curr_index = history.len();
+        let entry = HistoryEntry {
+            time: SystemTime::now(),
+            mode: self.mode.clone(),
+            line: new_line,
+        };
+        push_line(&mut history, entry, policy);
+        self.curr_index = self.view(&history).len();
+        self.draft = None;
     }
 
     /// Get previous line from `HISTORY` just above current index.
-    /// This will update current index in the scroll.
-    pub fn up_next(&mut self) -> Option<String> {
+    /// The `input` being typed is kept when scrolling up starts, to be got back
+    /// by scrolling down past the newest line.
+    /// Returns `None` at the oldest line, which keeps the current index.
+    pub fn up_next(&mut self, input: &str) -> Option<String> {
         let history = HISTORY.read().unwrap();
-        if self.curr_index == 0 || history.is_empty() {
+        let view = self.view(&history);
+        // the history may have been cleared or trimmed while scrolling
+        self.curr_index = self.curr_index.min(view.len());
+        if self.curr_index == 0 {
             return None;
         }
+        if self.curr_index == view.len() {
+            self.draft = Some(input.to_string());
+        }
         self.curr_index -= 1;
-        history.get(self.curr_index).cloned()
+        Some(history[view[self.curr_index]].line.clone())
     }
 
-    /// Get last line from `HISTORY` just below current index.
-    /// This will update current index in the scroll.
+    /// Get next line from `HISTORY` just below current index,
+    /// or the input kept by `up_next` when scrolling down past the newest line.
+    /// Returns `None` when not scrolling through the history.
     pub fn down_next(&mut self) -> Option<String> {
         let history = HISTORY.read().unwrap();
-        if self.curr_index >= history.len() {
+        let view = self.view(&history);
+        if self.curr_index >= view.len() {
+            self.curr_index = view.len();
             return None;
         }
         self.curr_index += 1;
-        history.get(self.curr_index).cloned()
+        match view.get(self.curr_index) {
+            Some(index) => Some(history[*index].line.clone()),
+            None => Some(self.draft.take().unwrap_or_default()),
+        }
+    }
+
+    /// Search backwards in `HISTORY` for the most recent line containing `substr`.
+    /// Only lines before `from_index` are searched, so passing the index of the
+    /// last match returns the next older match, and `usize::MAX` searches everything.
+    /// Returns the index of the matching line, which can be read with `get_line`.
+    /// This won't update current index in the scroll.
+    pub fn search_back(&self, substr: &str, from_index: usize) -> Option<usize> {
+        let history = HISTORY.read().unwrap();
+        let from_index = from_index.min(history.len());
+        history[..from_index]
+            .iter()
+            .rposition(|entry| entry.line.contains(substr))
+    }
+
+    /// Get a copy of all lines in `HISTORY`, from oldest to newest.
+    pub fn all(&self) -> Vec<String> {
+        let history = HISTORY.read().unwrap();
+        history.iter().map(|entry| entry.line.clone()).collect()
+    }
+
+    /// Get a copy of all lines in `HISTORY` with the time each was entered,
+    /// from oldest to newest.
+    pub fn all_timed(&self) -> Vec<(SystemTime, String)> {
+        let history = HISTORY.read().unwrap();
+        history
+            .iter()
+            .map(|entry| (entry.time, entry.line.clone()))
+            .collect()
+    }
+
+    /// Expand a history designator into the history line it refers to.
+    /// `!!` refers to the most recent line and `!N` to the N-th line (1-based)
+    /// as numbered by the `history` command.
+    /// Returns `None` if the designator refers to a line out of range.
+    pub fn expand(&self, designator: &str) -> Option<String> {
+        let history = HISTORY.read().unwrap();
+        if designator == "!!" {
+            return history.last().map(|entry| entry.line.clone());
+        }
+        let number = designator.strip_prefix('!')?.parse::<usize>().ok()?;
+        history
+            .get(number.checked_sub(1)?)
+            .map(|entry| entry.line.clone())
+    }
+
+    /// Remove all lines from `HISTORY`.
+    /// This will reset current index to the empty history.
+    pub fn clear(&mut self) {
+        let mut history = HISTORY.write().unwrap();
+        history.clear();
+        self.curr_index = 0;
+        self.draft = None;
+    }
+
+    /// Get the line at `index` in `HISTORY`.
+    /// This won't update current index in the scroll.
+    pub fn get_line(&self, index: usize) -> Option<String> {
+        let history = HISTORY.read().unwrap();
+        history.get(index).map(|entry| entry.line.clone())
+    }
+}
+
+/// Get the `history` command model/schema as a Clap command structure
+pub fn command() -> Command {
+    Command::new("history")
+        .disable_version_flag(true)
+        .disable_help_flag(true)
+        .disable_help_subcommand(true)
+        .about("Print command history")
+        .arg(
+            Arg::new("time")
+                .long("time")
+                .action(ArgAction::SetTrue)
+                .help("Show the time each command was entered, in UTC"),
+        )
+        .subcommands([
+            Command::new("clear").about("Clear command history"),
+            command_help(),
+        ])
+}
+
+/// Handle `history` command.
+pub fn run_command(args: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let mut history = HistoryHandle::get();
+    match args.first().map(String::as_str) {
+        None => {
+            for (i, line) in history.all().iter().enumerate() {
+                cliprintln!(writer, "{:>4}  {}", i + 1, line).unwrap();
+            }
+            Ok(CmdAction::Ok)
+        }
+        Some("--time") => {
+            for (i, (time, line)) in history.all_timed().iter().enumerate() {
+                let secs = time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                let time = util::format_timestamp(secs);
+                cliprintln!(writer, "{:>4}  {}  {}", i + 1, time, line).unwrap();
+            }
+            Ok(CmdAction::Ok)
+        }
+        Some("clear") => {
+            history.clear();
+            Ok(CmdAction::Ok)
+        }
+        Some("help") => {
+            print_help(&mut writer, &command(), &args[1..]);
+            Ok(CmdAction::Ok)
+        }
+        _ => Err(CmdError::Unhandled),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use crate::cli;
+    use crate::history::{
+        mode_view, push_line, HistoryDedup, HistoryEntry, HistoryHandle, HistoryPolicy,
+    };
+
+    fn entry(line: &str, mode: &[&str], time: SystemTime) -> HistoryEntry {
+        HistoryEntry {
+            time,
+            mode: mode.iter().map(|name| name.to_string()).collect(),
+            line: line.to_string(),
+        }
+    }
+
+    /// Start the global history over with the given lines, returning a handle to scroll it.
+    fn history_with(lines: &[&str]) -> HistoryHandle {
+        HistoryHandle::get().clear();
+        let mut history = HistoryHandle::get();
+        for line in lines {
+            history.add(line.to_string());
+        }
+        history
+    }
+
+    fn add_lines(lines: &[&str], policy: HistoryPolicy) -> Vec<String> {
+        let mut history = Vec::new();
+        for line in lines {
+            push_line(&mut history, entry(line, &[], SystemTime::now()), policy);
+        }
+        history.into_iter().map(|entry| entry.line).collect()
+    }
+
+    #[test]
+    fn test_dedup_none() {
+        let policy = HistoryPolicy {
+            dedup: HistoryDedup::None,
+            ..Default::default()
+        };
+        let history = add_lines(&["a", "a", "b", "a"], policy);
+        assert_eq!(history, vec!["a", "a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_dedup_consecutive() {
+        let history = add_lines(&["a", "a", "b", "a"], HistoryPolicy::default());
+        assert_eq!(history, vec!["a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_dedup_global() {
+        let policy = HistoryPolicy {
+            dedup: HistoryDedup::Global,
+            ..Default::default()
+        };
+        let history = add_lines(&["a", "b", "c", "a", "b"], policy);
+        assert_eq!(history, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_max_size_drops_oldest() {
+        let policy = HistoryPolicy {
+            max_size: Some(3),
+            ..Default::default()
+        };
+        let history = add_lines(&["a", "b", "c", "d", "e"], policy);
+        assert_eq!(history, vec!["c", "d", "e"]);
+        // a duplicate dropped doesn't push older lines out
+        let history = add_lines(&["a", "b", "c", "c"], policy);
+        assert_eq!(history, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_lines_keep_their_time() {
+        let mut history = Vec::new();
+        let policy = HistoryPolicy {
+            dedup: HistoryDedup::Global,
+            ..Default::default()
+        };
+        let time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        push_line(&mut history, entry("a", &[], time(10)), policy);
+        push_line(&mut history, entry("b", &[], time(20)), policy);
+        push_line(&mut history, entry("a", &[], time(30)), policy);
+        // a line entered again is kept with the time it was entered last
+        assert_eq!(
+            history,
+            vec![entry("b", &[], time(20)), entry("a", &[], time(30))]
+        );
+    }
+
+    #[test]
+    fn test_mode_view_filters_by_mode_prefix() {
+        let now = SystemTime::now();
+        let history = vec![
+            entry("change query", &[], now),
+            entry("show 1", &["change"], now),
+            entry("remote list", &[], now),
+            entry("sub", &["change", "sub"], now),
+        ];
+        let mode = vec!["change".to_string()];
+        assert_eq!(mode_view(&history, &mode, true), vec![1, 3]);
+        assert_eq!(mode_view(&history, &[], true), vec![0, 1, 2, 3]);
+        // without history per mode, or with no lines in the mode, all lines are scrolled
+        assert_eq!(mode_view(&history, &mode, false), vec![0, 1, 2, 3]);
+        let mode = vec!["remote".to_string()];
+        assert_eq!(mode_view(&history, &mode, true), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_same_line_in_other_mode_is_not_duplicate() {
+        let now = SystemTime::now();
+        let mut history = Vec::new();
+        let policy = HistoryPolicy::default();
+        push_line(&mut history, entry("help", &[], now), policy);
+        assert!(push_line(
+            &mut history,
+            entry("help", &["change"], now),
+            policy
+        ));
+        assert!(!push_line(
+            &mut history,
+            entry("help", &["change"], now),
+            policy
+        ));
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn test_up_from_empty_input() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let mut history = history_with(&[]);
+        assert_eq!(history.up_next(""), None);
+        assert_eq!(history.down_next(), None);
+        let mut history = history_with(&["a", "b"]);
+        assert_eq!(history.up_next("").as_deref(), Some("b"));
+        assert_eq!(history.down_next().as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_up_past_the_top() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let mut history = history_with(&["a", "b"]);
+        assert_eq!(history.up_next("").as_deref(), Some("b"));
+        assert_eq!(history.up_next("b").as_deref(), Some("a"));
+        assert_eq!(history.up_next("a"), None);
+        assert_eq!(history.up_next("a"), None);
+        // the oldest line is still where scrolling down starts from
+        assert_eq!(history.down_next().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_down_back_to_input() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let mut history = history_with(&["a", "b"]);
+        assert_eq!(history.up_next("change sh").as_deref(), Some("b"));
+        assert_eq!(history.up_next("b").as_deref(), Some("a"));
+        assert_eq!(history.down_next().as_deref(), Some("b"));
+        assert_eq!(history.down_next().as_deref(), Some("change sh"));
+        assert_eq!(history.down_next(), None);
+    }
+
+    #[test]
+    fn test_alternate_up_down() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let mut history = history_with(&["a", "b", "c"]);
+        assert_eq!(history.up_next("x").as_deref(), Some("c"));
+        assert_eq!(history.down_next().as_deref(), Some("x"));
+        assert_eq!(history.up_next("x").as_deref(), Some("c"));
+        assert_eq!(history.up_next("c").as_deref(), Some("b"));
+        assert_eq!(history.down_next().as_deref(), Some("c"));
+        assert_eq!(history.up_next("c").as_deref(), Some("b"));
+        assert_eq!(history.up_next("b").as_deref(), Some("a"));
+        // a line entered resets the scroll to the newest line
+        history.add("d".to_string());
+        assert_eq!(history.up_next("").as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn test_ignore_space() {
+        let policy = HistoryPolicy::default();
+        assert!(!policy.ignores(" change show 1"));
+        let policy = HistoryPolicy {
+            ignore_space: true,
+            ..Default::default()
+        };
+        assert!(policy.ignores(" change show 1"));
+        assert!(!policy.ignores("change show 1 "));
     }
 }
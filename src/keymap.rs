@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Editing actions of the prompt that keys are bound to.
+/// Characters typed with keys not bound to any action are inserted in the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Enter the input as a command line
+    Submit,
+    /// Quit the current mode when the input is empty, otherwise delete the character under the cursor
+    Quit,
+    /// Discard the input and start a new prompt
+    Cancel,
+    /// Erase the character before the cursor
+    Backspace,
+    /// Erase the word before the cursor
+    BackspaceWord,
+    /// Erase the character under the cursor
+    Delete,
+    /// Complete the input, or cycle forward through the candidates listed
+    Complete,
+    /// Cycle backwards through the candidates listed
+    CompleteBackwards,
+    ClearScreen,
+    HistoryUp,
+    HistoryDown,
+    /// Search the history backwards for lines containing the query typed
+    ReverseSearch,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    /// Erase from the start of the input to the cursor
+    KillToStart,
+    /// Erase from the cursor to the end of the input
+    KillLine,
+}
+
+impl Action {
+    /// Every action, as named in configuration.
+    pub const ALL: [Action; 20] = [
+        Action::Submit,
+        Action::Quit,
+        Action::Cancel,
+        Action::Backspace,
+        Action::BackspaceWord,
+        Action::Delete,
+        Action::Complete,
+        Action::CompleteBackwards,
+        Action::ClearScreen,
+        Action::HistoryUp,
+        Action::HistoryDown,
+        Action::ReverseSearch,
+        Action::Left,
+        Action::Right,
+        Action::WordLeft,
+        Action::WordRight,
+        Action::Home,
+        Action::End,
+        Action::KillToStart,
+        Action::KillLine,
+    ];
+
+    /// Get an action by its name in kebab-case, like `history-up`.
+    pub fn named(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// Name of the action in kebab-case, as given in configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Submit => "submit",
+            Action::Quit => "quit",
+            Action::Cancel => "cancel",
+            Action::Backspace => "backspace",
+            Action::BackspaceWord => "backspace-word",
+            Action::Delete => "delete",
+            Action::Complete => "complete",
+            Action::CompleteBackwards => "complete-backwards",
+            Action::ClearScreen => "clear-screen",
+            Action::HistoryUp => "history-up",
+            Action::HistoryDown => "history-down",
+            Action::ReverseSearch => "reverse-search",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::WordLeft => "word-left",
+            Action::WordRight => "word-right",
+            Action::Home => "home",
+            Action::End => "end",
+            Action::KillToStart => "kill-to-start",
+            Action::KillLine => "kill-line",
+        }
+    }
+}
+
+/// Bindings of keys, with their modifiers, to the actions of the prompt.
+/// The default keymap has emacs-like bindings, which can be overridden one by one
+/// from configuration, see [`Keymap::bind`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: HashMap<(KeyCode, KeyModifiers), Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let ctrl = KeyModifiers::CONTROL;
+        let alt = KeyModifiers::ALT;
+        let none = KeyModifiers::NONE;
+        let bindings = [
+            ((KeyCode::Enter, none), Action::Submit),
+            ((KeyCode::Char('d'), ctrl), Action::Quit),
+            ((KeyCode::Char('c'), ctrl), Action::Cancel),
+            ((KeyCode::Backspace, none), Action::Backspace),
+            ((KeyCode::Backspace, alt), Action::BackspaceWord),
+            ((KeyCode::Delete, none), Action::Delete),
+            ((KeyCode::Tab, none), Action::Complete),
+            ((KeyCode::BackTab, none), Action::CompleteBackwards),
+            ((KeyCode::Char('l'), ctrl), Action::ClearScreen),
+            ((KeyCode::Up, none), Action::HistoryUp),
+            ((KeyCode::Down, none), Action::HistoryDown),
+            ((KeyCode::Char('r'), ctrl), Action::ReverseSearch),
+            ((KeyCode::Left, none), Action::Left),
+            ((KeyCode::Right, none), Action::Right),
+            ((KeyCode::Left, alt), Action::WordLeft),
+            ((KeyCode::Left, ctrl), Action::WordLeft),
+            ((KeyCode::Left, alt | ctrl), Action::WordLeft),
+            ((KeyCode::Right, alt), Action::WordRight),
+            ((KeyCode::Right, ctrl), Action::WordRight),
+            ((KeyCode::Right, alt | ctrl), Action::WordRight),
+            ((KeyCode::Home, none), Action::Home),
+            ((KeyCode::Char('a'), ctrl), Action::Home),
+            ((KeyCode::End, none), Action::End),
+            ((KeyCode::Char('e'), ctrl), Action::End),
+            ((KeyCode::Char('u'), ctrl), Action::KillToStart),
+            ((KeyCode::Char('k'), ctrl), Action::KillLine),
+        ];
+        Keymap {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Bind a key, like `ctrl-p` or `alt-backspace`, to an action, replacing its binding if any.
+    pub fn bind(&mut self, key: &str, action: Action) -> Result<(), String> {
+        self.bindings.insert(parse_key(key)?, action);
+        Ok(())
+    }
+
+    /// Get the action a key press is bound to.
+    /// Keys with modifiers not bound on their own, like SHIFT+TAB, fall back to
+    /// the binding of the key alone.
+    pub fn action(&self, key_event: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .get(&(key_event.code, key_event.modifiers))
+            .or_else(|| self.bindings.get(&(key_event.code, KeyModifiers::NONE)))
+            .copied()
+    }
+}
+
+/// Parse a key given as its name preceded by modifiers, separated by `-`,
+/// like `ctrl-a`, `alt-left`, `shift-tab` or `x`.
+pub fn parse_key(key: &str) -> Result<(KeyCode, KeyModifiers), String> {
+    let invalid = || format!("invalid key '{}'", key);
+    // the key itself may be the dash, as in `ctrl--`
+    let (modifier_names, name) = match key.strip_suffix("--") {
+        Some(modifier_names) => (modifier_names, "-"),
+        None => key.rsplit_once('-').unwrap_or(("", key)),
+    };
+    let mut modifiers = KeyModifiers::NONE;
+    for modifier in modifier_names.split('-').filter(|name| !name.is_empty()) {
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => KeyModifiers::CONTROL,
+            "alt" | "meta" | "m" => KeyModifiers::ALT,
+            "shift" | "s" => KeyModifiers::SHIFT,
+            _ => return Err(invalid()),
+        };
+    }
+    let code = match name.to_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+            modifiers.remove(KeyModifiers::SHIFT);
+            KeyCode::BackTab
+        }
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "esc" | "escape" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                // terminals report CTRL and ALT letters in lowercase
+                (Some(c), None) if !modifiers.is_empty() => KeyCode::Char(c.to_ascii_lowercase()),
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(invalid()),
+            }
+        }
+    };
+    Ok((code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use crate::keymap::{parse_key, Action, Keymap};
+
+    #[test]
+    fn test_parse_key() {
+        let ctrl = KeyModifiers::CONTROL;
+        let alt = KeyModifiers::ALT;
+        assert_eq!(parse_key("ctrl-a"), Ok((KeyCode::Char('a'), ctrl)));
+        assert_eq!(parse_key("Ctrl-P"), Ok((KeyCode::Char('p'), ctrl)));
+        assert_eq!(parse_key("alt-backspace"), Ok((KeyCode::Backspace, alt)));
+        assert_eq!(parse_key("ctrl-alt-left"), Ok((KeyCode::Left, ctrl | alt)));
+        assert_eq!(
+            parse_key("shift-tab"),
+            Ok((KeyCode::BackTab, KeyModifiers::NONE))
+        );
+        assert_eq!(parse_key("ctrl--"), Ok((KeyCode::Char('-'), ctrl)));
+        assert_eq!(parse_key("J"), Ok((KeyCode::Char('J'), KeyModifiers::NONE)));
+        assert!(parse_key("hyper-a").is_err());
+        assert!(parse_key("ctrl-nope").is_err());
+        assert!(parse_key("").is_err());
+    }
+
+    #[test]
+    fn test_action_names() {
+        assert_eq!(Action::HistoryUp.name(), "history-up");
+        assert_eq!(Action::named("kill-line"), Some(Action::KillLine));
+        assert_eq!(Action::named("nope"), None);
+        for action in Action::ALL {
+            assert_eq!(Action::named(action.name()), Some(action));
+        }
+    }
+
+    #[test]
+    fn test_keymap_bindings() {
+        let press = |code, modifiers| KeyEvent::new(code, modifiers);
+        let mut keymap = Keymap::default();
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('a'), KeyModifiers::CONTROL)),
+            Some(Action::Home)
+        );
+        // SHIFT+TAB is reported with the modifier
+        assert_eq!(
+            keymap.action(&press(KeyCode::BackTab, KeyModifiers::SHIFT)),
+            Some(Action::CompleteBackwards)
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('p'), KeyModifiers::CONTROL)),
+            None
+        );
+        keymap.bind("ctrl-p", Action::HistoryUp).unwrap();
+        keymap.bind("ctrl-a", Action::KillLine).unwrap();
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('p'), KeyModifiers::CONTROL)),
+            Some(Action::HistoryUp)
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('a'), KeyModifiers::CONTROL)),
+            Some(Action::KillLine)
+        );
+        assert!(keymap.bind("nope-x", Action::Submit).is_err());
+    }
+}
@@ -1,19 +1,32 @@
 use std::fmt::Display;
+use std::fs::File;
 use std::io;
-use std::io::{ErrorKind, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::ops::ControlFlow;
+use std::time::Duration;
 
-use clap::Command;
-use crossterm::style::{Print, PrintStyledContent, Stylize};
+use clap::{Arg, ArgAction, Command};
+use crossterm::style::Print;
+use crossterm::tty::IsTty;
 use crossterm::{execute, queue};
+use gerlib::accounts::AccountEndpoints;
+use gerlib::config::ConfigEndpoints;
 use gerlib::GerritRestApi;
 
-use util::CmdAction;
+use util::{CmdAction, CmdError, ModeStack};
 
-use crate::cli::SmartNewLine;
+use crate::cli::{PrintStyled, SmartNewLine};
+use crate::config::Config;
+use crate::history::HistoryPolicy;
 
 mod change;
 mod cli;
+mod config;
+mod highlight;
 mod history;
+mod keymap;
+mod remote;
+mod theme;
 mod util;
 
 /// The ideia right now is to create a binary to start testing crossterm again
@@ -28,7 +41,7 @@ mod util;
 /// - [ ] Handle commands with Clap::App
 /// - [x] Handle scroll when cursor is at last row of the terminal window
 /// - [ ] Command History (clear HISTORY, navegate HISTORY, print HISTORY, auto save/load HISTORY)
-/// - [ ] Clear command should clear all lines up to the start of the command `gerrit`
+/// - [x] Clear command should clear all lines up to the start of the command `gerrit`
 ///       that means, clear until where the command `gerrit` was invoked.
 ///       example:
 ///       user@pc$ # other stuff          user@pc$ # other stuff
@@ -48,14 +61,14 @@ mod util;
 ///       Keep a new line count in CLI global struct and create cli::clear function
 ///       that abstracts the functionally.
 ///
-/// - [ ] Script as input to run automatically commands from a file
+/// - [x] Script as input to run automatically commands from a file
 /// - [x] HISTORY up/down with on-going command restore on last down-arrow
-/// - [ ] Handle left/right arrows and prompt in-middle insert characters,
+/// - [x] Handle left/right arrows and prompt in-middle insert characters,
 ///       prompt will have to shift the characters.
 /// - [x] Trim whitespace from user input text
 /// - [ ] Make a way to handle '\n' streamed to stdout using print!() as SmartNewLine() instead;
 /// - [ ] Match commands with a prefix tree (use trie-rs?) and give completion suggestions.
-/// - [ ] On program abort, add hook to restore terminal to normal in order to
+/// - [x] On program abort, add hook to restore terminal to normal in order to
 ///       print panic output message properly new new lines and all.
 /// - [ ] SmartMoveLeft: because of wrapped text
 ///       check for screen column 0 then should MoveUp and MoveToColumn(max).
@@ -65,8 +78,8 @@ mod util;
 ///       We can then save the full command name in history, and a full match is found.
 /// - [ ] TAB command completion
 /// - [ ] Cli mode set. Example 'gerrit>change<CR>' -> 'change>'
-/// - [ ] Directly run commands from program invocation args (main args) and quit.
-/// - [ ] Display auto logged-in user and remote info in a Banner from program start
+/// - [x] Directly run commands from program invocation args (main args) and quit.
+/// - [x] Display auto logged-in user and remote info in a Banner from program start
 ///       Similar to linux login info banner.
 ///       Create login auto start config for enabling that.
 /// - [ ] Maybe this prefix+symbol could be a func param only of prompt();
@@ -77,89 +90,486 @@ mod util;
 ///         2 139721  NEW  New footer design
 ///         3 139453  NEW  Support new SDK version
 ///         gerrit>show #1
-/// - [ ] Read & Run commands from stdin, then quit.
+/// - [x] Read & Run commands from stdin, then quit.
 ///       Example: echo -e 'change' | gerrit
 ///
 fn main() -> std::io::Result<()> {
     pretty_env_logger::init_custom_env("GERRIT_LOG");
 
-    let _cli_guard = cli::initialize();
-    cli::set_prefix("gerrit".to_string().stylize());
-    cli::set_symbol(">".to_string().green());
+    // parse invocation args before the terminal goes raw so clap can print errors normally
+    let matches = program_command().get_matches();
+
+    let os_args = matches
+        .get_many::<String>("COMMAND")
+        .map(|values| values.cloned().collect::<Vec<String>>())
+        .unwrap_or_default();
+
+    // commands are read from args, a script or stdin instead of the prompt when not interactive
+    let interactive =
+        std::io::stdin().is_tty() && !matches.contains_id("script") && os_args.is_empty();
+    let _cli_guard = if interactive {
+        match cli::initialize() {
+            Ok(cli_guard) => cli_guard,
+            Err(err) => {
+                eprintln!("Cannot start the interactive shell: {}", err);
+                eprintln!(
+                    "Give commands as arguments, in a script with --script FILE, or on stdin instead"
+                );
+                return Err(err);
+            }
+        }
+    } else {
+        cli::initialize_plain()
+    };
+    // any non-empty NO_COLOR disables colors, see https://no-color.org
+    let no_color = std::env::var("NO_COLOR").is_ok_and(|value| !value.is_empty());
+    cli::set_color(!no_color && !matches.get_flag("no-color"));
 
     let mut writer = cli::stdout();
 
-    let url = std::env::var("GERRIT_URL");
-    let user = std::env::var("GERRIT_USER");
-    let http_pw = std::env::var("GERRIT_PW");
-    if url.is_err() || user.is_err() || http_pw.is_err() {
-        cliprintln!(writer, "Please set ENV VARS").unwrap();
-        return Err(io::Error::from(ErrorKind::PermissionDenied));
+    let config = Config::load().unwrap_or_else(|err| {
+        cliprintln!(writer, "Failed to load config: {}", err).unwrap();
+        Config::default()
+    });
+    match config.theme() {
+        Ok(theme) => theme::set_theme(theme),
+        Err(err) => cliprintln!(writer, "Failed to load config: {}", err).unwrap(),
+    }
+    cli::set_prefix(theme::prefix("gerrit"));
+    cli::set_symbol(theme::symbol(">"));
+    remote::update_prompt(&config);
+    cli::set_fuzzy_completion(config.fuzzy_completion);
+    cli::set_bell(config.bell);
+    cli::set_confirm_quit(config.confirm_quit);
+    match config.keymap() {
+        Ok(keymap) => cli::set_keymap(keymap),
+        Err(err) => cliprintln!(writer, "Failed to load config: {}", err).unwrap(),
+    }
+    history::set_policy(HistoryPolicy {
+        dedup: config.history_dedup,
+        max_size: config.history_size,
+        ignore_space: config.history_ignore_space,
+        per_mode: config.history_per_mode,
+    });
+    util::set_spinner_style(config.spinner);
+    util::set_spinner_delay(Duration::from_millis(
+        config.spinner_delay_ms.unwrap_or(1000),
+    ));
+    match config.status_colors() {
+        Ok(colors) => change::set_status_colors(colors),
+        Err(err) => cliprintln!(writer, "Failed to load config: {}", err).unwrap(),
     }
 
-    let mut gerrit = GerritRestApi::new(
-        url.unwrap().parse().unwrap(),
-        user.unwrap().as_str(),
-        http_pw.unwrap().as_str(),
-    )
-    .unwrap()
-    .ssl_verify(false)
-    .unwrap();
-
-    let os_args = std::env::args().collect::<Vec<String>>()[1..].to_vec();
-    let mut handled_os_args = false;
+    remote::set_insecure(matches.get_flag("insecure"));
+    match (config.request_timeout(), config.request_retries()) {
+        (Ok(timeout), Ok(retries)) => remote::set_request_policy(timeout, retries),
+        (Err(err), _) | (_, Err(err)) => {
+            cliprintln!(writer, "Failed to load config: {}", err).unwrap()
+        }
+    }
+    let settings = match config.connection_settings() {
+        Ok(settings) => settings,
+        Err(err) => {
+            cliprintln!(writer, "Cannot connect: {}", err).unwrap();
+            return Err(io::Error::from(ErrorKind::PermissionDenied));
+        }
+    };
+    let url = settings.url.clone();
+    let mut gerrit = match remote::connect(&settings) {
+        Ok(gerrit) => gerrit,
+        Err(err) => {
+            cliprintln!(writer, "Failed to connect to {}: {}", url, err).unwrap();
+            return Err(io::Error::new(ErrorKind::Other, err));
+        }
+    };
 
     let cmd_schema_root = command();
-    let mut fixed_args = Vec::new();
+    util::set_dynamic_values("ID", change::cached_change_values);
+    util::set_dynamic_values("FILE", change::cached_file_values);
+    util::set_dynamic_values("QUERY", change::query_presets);
+    util::set_dynamic_values("run", change::saved_query_names);
+    util::set_dynamic_values("project", change::cached_project_values);
+    util::set_dynamic_values("branch", change::cached_branch_values);
+
+    if let Some(script) = matches.get_one::<String>("script") {
+        let strict = matches.get_flag("strict");
+        return run_script(script, strict, &cmd_schema_root, &mut gerrit);
+    }
+
+    if !os_args.is_empty() {
+        let mut modes = ModeStack::default();
+        let ret = run_unchecked_line(&os_args, &mut modes, &cmd_schema_root, &mut gerrit);
+        if let ControlFlow::Continue(Err(())) = ret {
+            return Err(io::Error::new(ErrorKind::Other, "command failed"));
+        }
+        return Ok(());
+    }
+
+    if !interactive {
+        return run_stdin(&cmd_schema_root, &mut gerrit);
+    }
+
+    if std::env::var("GERRIT_BANNER").is_ok_and(|banner| banner == "1") {
+        print_banner(&mut writer, &mut gerrit, url.as_str());
+    }
+
+    let mut modes = ModeStack::default();
+    if config.restore_modes {
+        restore_modes(&config.modes, &cmd_schema_root, &mut modes, &mut gerrit);
+    }
     loop {
-        if handled_os_args {
+        let curr_cmd_schema = current_command(&cmd_schema_root, &mut modes);
+        let new_args = cli::prompt(curr_cmd_schema)?;
+        if run_line(new_args.as_slice(), &mut modes, &mut gerrit).is_break() {
             break;
         }
-        let new_args = if os_args.is_empty() {
-            let curr_cmd_schema = util::find_command(&cmd_schema_root, fixed_args.as_slice());
-            cli::prompt(curr_cmd_schema)?
-        } else {
-            handled_os_args = true;
-            os_args.clone()
-        };
-        // first level commands
-        let cmd = new_args.first().unwrap();
-        match cmd.as_str() {
-            "quit" => break,
-            "exit" => {
-                if fixed_args.is_empty() {
-                    break;
-                } else {
-                    fixed_args.clear();
-                    cli::set_prefix("gerrit".to_string().stylize());
-                    continue;
-                }
-            }
-            _ => {}
-        }
-        // fixed args defined by mode are joined with new args and
-        // handled down the command tree path as an all-in-one input line from user
-        let mut all_args = fixed_args.clone();
-        all_args.extend_from_slice(new_args.as_slice());
-        // second level commands
-        let subcmd_ret = run_subcommand(all_args.as_slice(), &mut gerrit);
-        if let Ok(action) = subcmd_ret {
+    }
+    if config.restore_modes {
+        save_modes(&modes);
+    }
+    Ok(())
+}
+
+/// Get the program invocation model/schema as a Clap command structure.
+/// This is different from the `gerrit` command schema used in the prompt,
+/// it describes the options given to the program from the shell.
+fn program_command() -> Command {
+    Command::new("gerrit")
+        .about("Gerrit command line interface")
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .value_name("FILE")
+                .help("Run commands from a script file and quit"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .help("Stop the script with an error on the first failed command"),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .action(ArgAction::SetTrue)
+                .help("Print output without colors, also disabled by NO_COLOR"),
+        )
+        .arg(
+            Arg::new("insecure")
+                .long("insecure")
+                .action(ArgAction::SetTrue)
+                .help("Skip SSL certificate verification of the remote"),
+        )
+        .arg(
+            Arg::new("COMMAND")
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .help("Command to run directly and quit"),
+        )
+}
+
+/// Run a line of arguments given by the user in the current mode.
+/// First level commands (`quit`, `exit`) are handled here,
+/// the rest is dispatched to the subcommands down the command tree.
+/// Entering and exiting modes pushes and pops the mode stack, along with the prompt prefix.
+/// Returns `ControlFlow::Break` when the program should quit,
+/// otherwise whether the command was handled, having already reported the exception if not.
+fn run_line(
+    new_args: &[String],
+    modes: &mut ModeStack,
+    gerrit: &mut GerritRestApi,
+) -> ControlFlow<(), Result<(), ()>> {
+    // args of the current mode are joined with new args and
+    // handled down the command tree path as an all-in-one input line from user,
+    // with the commands given by alias replaced by their names for dispatching
+    let mut all_args = modes.args().to_vec();
+    all_args.extend_from_slice(new_args);
+    let cmd_schema_root = command();
+    let all_args = util::resolve_aliases(&cmd_schema_root, &all_args);
+    // first level commands, `exit` within a mode is handled by the mode's command
+    let cmd = &all_args[modes.args().len()];
+    match cmd.as_str() {
+        "quit" => return ControlFlow::Break(()),
+        "exit" if modes.is_empty() => return ControlFlow::Break(()),
+        "reset" => {
+            reset_session(modes);
+            return ControlFlow::Continue(Ok(()));
+        }
+        _ => {}
+    }
+    // malformed commands are reported as the schema describes them, without running
+    if let Err(CmdError::Failed(message)) = util::validate_args(&cmd_schema_root, &all_args) {
+        print_exception(&mut cli::stdout(), message.as_str());
+        return ControlFlow::Continue(Err(()));
+    }
+    // second level commands
+    let subcmd_ret = run_subcommand(all_args.as_slice(), gerrit);
+    let exception = match subcmd_ret {
+        Ok(action) => {
             match action {
                 CmdAction::Ok => {}
-                CmdAction::EnterMode(str) => {
-                    fixed_args = all_args;
-                    cli::set_prefix(str.stylize());
+                CmdAction::EnterMode(prefix) => modes.push(all_args, prefix),
+                CmdAction::ExitMode => {
+                    modes.pop();
                 }
             }
-            continue;
+            return ControlFlow::Continue(Ok(()));
         }
         // registered command was not handled
-        let exception = format!("unhandled command! '{}'", cmd);
-        print_exception(&mut writer, exception.as_str());
+        Err(CmdError::Unhandled) => format!("unhandled command! '{}'", cmd),
+        Err(CmdError::Failed(message)) => message,
+    };
+    print_exception(&mut cli::stdout(), exception.as_str());
+    ControlFlow::Continue(Err(()))
+}
+
+/// Reset the session state: forget the cached changes and exit all modes back to the root.
+fn reset_session(modes: &mut ModeStack) {
+    change::cache_changes(Vec::new());
+    modes.clear();
+    cli::set_prefix(theme::prefix("gerrit"));
+    cliprintln!(cli::stdout(), "Session reset").unwrap();
+}
+
+/// Enter again the modes saved when the program quit last time, see [`save_modes`].
+/// Each mode is entered by running its command, like typed in the prompt.
+/// If a mode is no longer a command that enters a mode, it's reported and
+/// the prompt starts at the root instead.
+fn restore_modes(
+    saved_modes: &[Vec<String>],
+    cmd_schema_root: &Command,
+    modes: &mut ModeStack,
+    gerrit: &mut GerritRestApi,
+) {
+    for args in saved_modes {
+        let args = &util::resolve_aliases(cmd_schema_root, args);
+        let action = match util::find_command(cmd_schema_root, args) {
+            Some(_) => run_subcommand(args, gerrit),
+            None => Err(CmdError::Unhandled),
+        };
+        if let Ok(CmdAction::EnterMode(name)) = action {
+            modes.push(args.clone(), name);
+            continue;
+        }
+        let exception = format!("cannot restore mode '{}', starting at root", args.join(" "));
+        print_exception(&mut cli::stdout(), exception.as_str());
+        modes.clear();
+        return;
+    }
+}
+
+/// Save the modes the program is in to the configuration, to restore them next time.
+fn save_modes(modes: &ModeStack) {
+    let mut writer = cli::stdout();
+    let result = Config::load().and_then(|mut config| {
+        config.modes = modes.all_args();
+        config.save()
+    });
+    if let Err(err) = result {
+        cliprintln!(writer, "Failed to save modes: {}", err).unwrap();
+    }
+}
+
+/// Like [`run_line`] but for arguments that didn't come from the prompt,
+/// thus were not validated against the command schema yet.
+/// The first argument must be a command of the current mode, else it's reported as invalid.
+fn run_unchecked_line(
+    new_args: &[String],
+    modes: &mut ModeStack,
+    cmd_schema_root: &Command,
+    gerrit: &mut GerritRestApi,
+) -> ControlFlow<(), Result<(), ()>> {
+    let curr_cmd_schema = current_command(cmd_schema_root, modes);
+    if curr_cmd_schema.find_subcommand(&new_args[0]).is_none() {
+        let exception = format!("invalid input '{}'", new_args.join(" "));
+        print_exception(&mut cli::stdout(), exception.as_str());
+        return ControlFlow::Continue(Err(()));
     }
+    run_line(new_args, modes, gerrit)
+}
+
+/// Get the command schema of the current mode.
+/// If the mode points at a command that doesn't exist, the exception is reported
+/// and all modes are exited, falling back to the root schema.
+fn current_command<'a>(cmd_schema_root: &'a Command, modes: &mut ModeStack) -> &'a Command {
+    if let Some(cmd_schema) = util::find_command(cmd_schema_root, modes.args()) {
+        return cmd_schema;
+    }
+    let exception = format!("unknown mode '{}', back to root", modes.args().join(" "));
+    print_exception(&mut cli::stdout(), exception.as_str());
+    modes.clear();
+    cmd_schema_root
+}
+
+/// Run commands from a script file, one command per line, as if typed in the prompt.
+/// A failed command is reported and the script goes on to the next line,
+/// unless `strict` is set, then the script stops and an error is returned.
+fn run_script(
+    path: &str,
+    strict: bool,
+    cmd_schema_root: &Command,
+    gerrit: &mut GerritRestApi,
+) -> io::Result<()> {
+    let script = BufReader::new(File::open(path)?);
+    run_commands(script, path, strict, cmd_schema_root, gerrit)?;
     Ok(())
 }
 
+/// Run commands piped to stdin, one command per line, then quit.
+/// Every command is run even if one fails, but then an error is returned at the end
+/// so the program exits with non-zero status.
+fn run_stdin(cmd_schema_root: &Command, gerrit: &mut GerritRestApi) -> io::Result<()> {
+    let stdin = io::stdin().lock();
+    if run_commands(stdin, "stdin", false, cmd_schema_root, gerrit)? {
+        Ok(())
+    } else {
+        Err(io::Error::new(ErrorKind::Other, "some commands failed"))
+    }
+}
+
+/// Run commands read line by line from `reader`, as if typed in the prompt.
+/// Empty lines and lines starting with `#` are skipped.
+/// A failed command is reported with the line it came from in `source`.
+/// If `strict` is set, the first failed command stops the run with an error.
+/// Returns whether all commands succeeded.
+fn run_commands(
+    reader: impl BufRead,
+    source: &str,
+    strict: bool,
+    cmd_schema_root: &Command,
+    gerrit: &mut GerritRestApi,
+) -> io::Result<bool> {
+    let mut modes = ModeStack::default();
+    let mut all_succeeded = true;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // quoted text is kept in one argument, as in the prompt
+        let result = match util::split_words(line) {
+            Ok(words) => {
+                let new_args: Vec<String> = words.into_iter().map(|word| word.value).collect();
+                match run_unchecked_line(&new_args, &mut modes, cmd_schema_root, gerrit) {
+                    ControlFlow::Break(()) => break,
+                    ControlFlow::Continue(result) => result,
+                }
+            }
+            Err(err) => {
+                cli::print_invalid_input(&mut cli::stdout(), &err);
+                Err(())
+            }
+        };
+        if result.is_err() {
+            all_succeeded = false;
+            if strict {
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    format!("{}:{}: command failed", source, line_idx + 1),
+                ));
+            }
+        }
+    }
+    Ok(all_succeeded)
+}
+
+/// Display a login banner with the authenticated account, the remote and the server version.
+/// If the account can't be fetched, a short warning is displayed instead,
+/// so the shell can still be used.
+fn print_banner(writer: &mut impl Write, gerrit: &mut GerritRestApi, url: &str) {
+    let account = match remote::run_request(gerrit, |gerrit| gerrit.get_account("self")) {
+        Ok(account) => account,
+        Err(err) => {
+            queue!(
+                writer,
+                PrintStyled(theme::warning("Warning:")),
+                Print(" could not fetch logged in account from "),
+                Print(url),
+                Print(": "),
+                Print(failure_message(err)),
+                SmartNewLine(1)
+            )
+            .unwrap();
+            return;
+        }
+    };
+    queue!(
+        writer,
+        Print("Logged in as "),
+        PrintStyled(theme::name(account.username.unwrap_or_default())),
+    )
+    .unwrap();
+    if let Some(name) = account.name {
+        queue!(writer, Print(format!(" ({})", name))).unwrap();
+    }
+    queue!(
+        writer,
+        SmartNewLine(1),
+        Print("Remote "),
+        PrintStyled(theme::number(url)),
+    )
+    .unwrap();
+    if let Ok(version) = remote::run_request(gerrit, |gerrit| gerrit.get_version()) {
+        queue!(writer, Print(format!(" running Gerrit {}", version))).unwrap();
+    }
+    execute!(writer, SmartNewLine(1), SmartNewLine(1)).unwrap();
+}
+
+/// Display the account the remote authenticated with.
+/// Only the details the server returns are displayed.
+fn whoami(gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let loading = util::loading();
+    let account = remote::run_request(gerrit, |gerrit| gerrit.get_account("self"));
+    loading.stop();
+    let account = account?;
+    let fields = [
+        ("username", account.username),
+        ("name", account.name),
+        ("email", account.email),
+        ("id", Some(account.account_id.to_string())),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            let label = format!("{}:", field);
+            queue!(
+                writer,
+                Print(format!("{:10}{}", label, value)),
+                SmartNewLine(1)
+            )
+            .unwrap();
+        }
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Display the version of this program and of the Gerrit server.
+/// The client version is displayed even if the server version can't be fetched.
+fn print_version(writer: &mut impl Write, gerrit: &mut GerritRestApi) {
+    cliprintln!(writer, "client version: {}", env!("CARGO_PKG_VERSION")).unwrap();
+    let loading = util::loading();
+    let version = remote::run_request(gerrit, |gerrit| gerrit.get_version());
+    loading.stop();
+    match version {
+        Ok(version) => cliprintln!(writer, "server version: {}", version).unwrap(),
+        Err(err) => {
+            cliprintln!(writer, "server version: unknown, {}", failure_message(err)).unwrap()
+        }
+    }
+}
+
+/// Message of a request to the remote that failed, see [`remote::run_request`].
+fn failure_message(err: CmdError) -> String {
+    match err {
+        CmdError::Failed(message) => message,
+        CmdError::Unhandled => "request failed".to_string(),
+    }
+}
+
 /// Get the `gerrit` command model/schema as a Clap command structure
 fn command() -> Command {
     Command::new("gerrit")
@@ -167,10 +577,14 @@ fn command() -> Command {
         .disable_help_flag(true)
         .disable_help_subcommand(true)
         .subcommands([
-            change::command(),
-            Command::new("remote").about("Remote commands"),
+            change::command().alias("c"),
+            history::command(),
+            Command::new("clear").about("Clear the screen"),
+            remote::command(),
             Command::new("reset").about("Reset everything temporarily"),
-            Command::new("help").alias("?").about("Print command help"),
+            Command::new("whoami").about("Show the account logged in"),
+            Command::new("version").about("Show the client and server versions"),
+            command_help(),
             Command::new("exit").about("Exit from current mode"),
             Command::new("quit").about("Quit the program"),
         ])
@@ -178,22 +592,134 @@ fn command() -> Command {
 
 /// Match prompt against subcommands.
 /// Run matched subcommand and return result.
-fn run_subcommand(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, ()> {
+fn run_subcommand(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
     let (cmd, cmd_args) = args.split_first().unwrap();
     match cmd.as_str() {
-        "remote" => remote_run_command(),
+        "remote" => remote::run_command(cmd_args, gerrit),
         "change" => change::run_command(cmd_args, gerrit),
-        "help" | "?" => {
-            print_help(&mut cli::stdout(), &command());
+        "history" => history::run_command(cmd_args),
+        "whoami" => whoami(gerrit),
+        "version" => {
+            print_version(&mut cli::stdout(), gerrit);
+            Ok(CmdAction::Ok)
+        }
+        "clear" => {
+            cli::clear();
             Ok(CmdAction::Ok)
         }
-        _ => Err(()),
+        "help" => {
+            print_help(&mut cli::stdout(), &command(), cmd_args);
+            Ok(CmdAction::Ok)
+        }
+        _ => Err(CmdError::Unhandled),
     }
 }
 
+/// Get the `help` command schema, the same in every mode.
+pub fn command_help() -> Command {
+    Command::new("help")
+        .alias("?")
+        .about("Print command help")
+        .arg(
+            Arg::new("COMMAND")
+                .num_args(0..)
+                .help("Command to print the help of"),
+        )
+}
+
 /// Display help
-/// This should basically print out the command list and that's it.
-fn print_help(write: &mut impl Write, cmd_app: &Command) {
+/// Without a target, print out the command list of `cmd_app`.
+/// With a target, e.g. `show` for `help show`, print out the usage, arguments and
+/// subcommands of that command, descending from `cmd_app`.
+/// An unknown target prints out the command list with a note.
+fn print_help(write: &mut impl Write, cmd_app: &Command, target: &[String]) {
+    if !target.is_empty() {
+        if let Some(cmd) = util::find_command(cmd_app, target) {
+            // name the command path as in the schema, not as typed, which may be aliases
+            let mut path = Vec::new();
+            let mut curr_cmd = cmd_app;
+            for name in target {
+                curr_cmd = util::find_subcommand(curr_cmd, name).unwrap();
+                path.push(curr_cmd.get_name());
+            }
+            print_command_help(write, cmd, &path.join(" "));
+            return;
+        }
+        let note = format!("unknown command: {}", target.join(" "));
+        queue!(write, PrintStyled(theme::failure(note)), SmartNewLine(1)).unwrap();
+    }
+    print_command_list(write, cmd_app);
+}
+
+/// Print out the usage line, description, arguments and subcommands of a command.
+fn print_command_help(write: &mut impl Write, cmd: &Command, path: &str) {
+    let usage = cmd.clone().bin_name(path).render_usage().to_string();
+    queue!(write, Print(usage), SmartNewLine(1)).unwrap();
+    if let Some(about) = cmd.get_about() {
+        queue!(write, Print(about), SmartNewLine(1)).unwrap();
+    }
+    let args: Vec<_> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .map(|arg| (arg_usage(arg), arg))
+        .collect();
+    if !args.is_empty() {
+        queue!(write, SmartNewLine(1), Print("Arguments:"), SmartNewLine(1)).unwrap();
+    }
+    // line up the descriptions like in the command list, further if any usage is longer
+    let width = args
+        .iter()
+        .map(|(usage, _)| usage.len())
+        .max()
+        .unwrap_or(0)
+        .max(12);
+    for (usage, arg) in args {
+        let mut line = format!(" {:width$} {}", usage, arg.get_help().unwrap_or_default());
+        if arg.is_required_set() {
+            line.push_str(" (required)");
+        }
+        let values: Vec<_> = arg
+            .get_possible_values()
+            .iter()
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if arg.get_action().takes_values() && !values.is_empty() {
+            line.push_str(&format!(" [possible values: {}]", values.join(", ")));
+        }
+        queue!(write, Print(line.trim_end()), SmartNewLine(1)).unwrap();
+    }
+    if cmd.has_subcommands() {
+        queue!(write, SmartNewLine(1), Print("Commands:"), SmartNewLine(1)).unwrap();
+        print_command_list(write, cmd);
+    } else {
+        execute!(write, SmartNewLine(1)).unwrap();
+    }
+}
+
+/// Get how an argument is given, the way Clap shows it in the usage line,
+/// e.g. `<ID>`, `[QUERY]...` or `--limit <N>`.
+fn arg_usage(arg: &Arg) -> String {
+    let value_name = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(|| arg.get_id().to_string(), |name| name.to_string());
+    let mut usage = match arg.get_long() {
+        Some(long) if arg.get_action().takes_values() => format!("--{} <{}>", long, value_name),
+        Some(long) => format!("--{}", long),
+        None if arg.is_required_set() => format!("<{}>", value_name),
+        None => format!("[{}]", value_name),
+    };
+    let multiple = arg
+        .get_num_args()
+        .is_some_and(|range| range.max_values() > 1);
+    if multiple {
+        usage.push_str("...");
+    }
+    usage
+}
+
+/// Print out the list of subcommands of a command with their description.
+fn print_command_list(write: &mut impl Write, cmd_app: &Command) {
     for cmd in cmd_app.get_subcommands() {
         let line = format!(
             " {:6}       {}",
@@ -212,20 +738,156 @@ fn print_help(write: &mut impl Write, cmd_app: &Command) {
 fn print_exception<D: Display>(writer: &mut impl Write, str: D) {
     execute!(
         writer,
-        PrintStyledContent(format!("Exception: {}", str).black().on_red())
+        PrintStyled(theme::error(format!("Exception: {}", str))),
+        SmartNewLine(1)
     )
     .unwrap();
 }
 
-/// Handle `remote` command.
-/// NOTE: Temporary function place.
-fn remote_run_command() -> Result<CmdAction, ()> {
-    let mut stdout = cli::stdout();
-    let url = std::env::var("GERRIT_URL");
-    if let Ok(url) = url {
-        execute!(stdout, Print("remote url: "), Print(url), SmartNewLine(1),).unwrap()
-    } else {
-        cliprintln!(stdout, "no remotes configured").unwrap()
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Stylize;
+    use gerlib::changes::ChangeInfo;
+
+    use crate::util::ModeStack;
+    use crate::{change, cli, command, current_command, print_help, reset_session, util};
+
+    #[test]
+    fn test_reset_returns_to_root() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        change::cache_changes(vec![ChangeInfo::default()]);
+        cli::set_prefix("other".to_string().stylize());
+        let mut modes = ModeStack::default();
+        modes.push(vec!["change".to_string()], "change".to_string());
+        assert_eq!(cli::get_prefix(), "other/change");
+        reset_session(&mut modes);
+        assert!(change::cached_change_values().is_empty());
+        assert!(modes.is_empty());
+        assert!(modes.args().is_empty());
+        assert_eq!(cli::get_prefix(), "gerrit");
+    }
+
+    #[test]
+    fn test_validate_command_lines() {
+        let validate = |line: &str| {
+            let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+            util::validate_args(&command(), &args)
+        };
+        assert!(validate("change show 12 --patchset 2").is_ok());
+        assert!(validate("change query -is:wip owner:self --limit 5").is_ok());
+        assert!(validate("change review 12 --code-review -2").is_ok());
+        assert!(validate("help change show").is_ok());
+        assert!(validate("change show").is_err());
+        assert!(validate("change show 12 --patchset two").is_err());
+        assert!(validate("change review 12 --code-review 3").is_err());
+        assert!(validate("remote add work").is_err());
+        assert!(validate("whoami now").is_err());
+    }
+
+    fn help_output(target: &[&str]) -> String {
+        let target: Vec<String> = target.iter().map(|word| word.to_string()).collect();
+        let mut output = Vec::new();
+        let _no_color = cli::no_color();
+        print_help(&mut output, &command(), &target);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_help_of_subcommand() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let output = help_output(&["change", "show"]);
+        assert!(output.contains("Usage: change show"));
+        assert!(output.contains("<ID>"));
+        assert!(output.contains("Display change info"));
+        let output = help_output(&["change", "query"]);
+        assert!(output.contains("[possible values: updated, number, status, subject]"));
+        // aliases are resolved to the command names
+        let output = help_output(&["?"]);
+        assert!(output.contains("Usage: help"));
+    }
+
+    #[test]
+    fn test_help_of_arguments() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let output = help_output(&["change", "show"]);
+        let id_line = output
+            .lines()
+            .find(|line| line.starts_with(" <ID>"))
+            .unwrap();
+        assert!(id_line.ends_with("(required)"));
+        let output = help_output(&["change", "query"]);
+        let lines: Vec<&str> = output.lines().collect();
+        let query_line = lines
+            .iter()
+            .find(|line| line.starts_with(" [QUERY]..."))
+            .unwrap();
+        assert!(!query_line.contains("(required)"));
+        let limit_line = lines
+            .iter()
+            .find(|line| line.starts_with(" --limit <N>"))
+            .unwrap();
+        let sort_line = lines
+            .iter()
+            .find(|line| line.starts_with(" --sort <FIELD>"))
+            .unwrap();
+        // descriptions start on the same column
+        assert_eq!(limit_line.find("Maximum"), sort_line.find("Sort"));
+        let reverse_line = lines
+            .iter()
+            .find(|line| line.starts_with(" --reverse"))
+            .unwrap();
+        assert!(!reverse_line.contains("possible values"));
+    }
+
+    #[test]
+    fn test_help_of_unknown_command_lists_commands() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let output = help_output(&["change", "nope"]);
+        assert!(output.contains("unknown command: change nope"));
+        assert!(output.contains("remote"));
+        assert!(output.contains("quit"));
+        assert_eq!(
+            help_output(&[]),
+            help_output(&["nope"]).split_once('\n').unwrap().1
+        );
+    }
+
+    #[test]
+    fn test_aliases_resolve_to_command_names() {
+        let to_args = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let cmd_schema_root = command();
+        assert_eq!(
+            util::resolve_aliases(&cmd_schema_root, &to_args(&["c", "show", "1"])),
+            to_args(&["change", "show", "1"])
+        );
+        assert_eq!(
+            util::resolve_aliases(&cmd_schema_root, &to_args(&["c", "?", "c"])),
+            to_args(&["change", "help", "c"])
+        );
+        // a mode entered by alias is the same mode
+        let mut modes = ModeStack::default();
+        modes.push(to_args(&["c"]), "change".to_string());
+        assert_eq!(
+            current_command(&cmd_schema_root, &mut modes).get_name(),
+            "change"
+        );
+    }
+
+    #[test]
+    fn test_unknown_mode_falls_back_to_root() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let cmd_schema_root = command();
+        let mut modes = ModeStack::default();
+        modes.push(vec!["change".to_string()], "change".to_string());
+        assert_eq!(
+            current_command(&cmd_schema_root, &mut modes).get_name(),
+            "change"
+        );
+        modes.push(vec!["nope".to_string()], "nope".to_string());
+        assert_eq!(
+            current_command(&cmd_schema_root, &mut modes).get_name(),
+            "gerrit"
+        );
+        assert!(modes.is_empty());
     }
-    Ok(CmdAction::Ok)
 }
@@ -0,0 +1,617 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Arg, Command};
+use crossterm::style::Print;
+use crossterm::{execute, queue};
+use gerlib::GerritRestApi;
+use once_cell::sync::Lazy;
+use parking_lot::ReentrantMutex;
+
+use crate::cli::{PrintStyled, SmartNewLine};
+use crate::config::{load_config, save_config, Config, ConnectionSettings, RemoteConfig};
+use crate::util::{CmdAction, CmdError};
+use crate::{cli, cliprintln, command_help, print_help, theme};
+
+static REMOTE_CONTEXT: Lazy<ReentrantMutex<RefCell<RemoteContext>>> =
+    Lazy::new(|| ReentrantMutex::new(RefCell::new(RemoteContext::default())));
+
+/// How often a pending request checks for completion, timeout and Ctrl+C.
+const REQUEST_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time to wait before the first retry of a failed request, doubled for every next retry.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct RemoteContext {
+    /// URL of the remote connected to
+    url: String,
+    /// Settings of the remote connected to, to connect again
+    settings: Option<ConnectionSettings>,
+    /// Skip SSL certificate verification of all remotes, regardless of settings
+    insecure: bool,
+    /// Timeout and retries of requests, see [`run_request`]
+    policy: RequestPolicy,
+    /// Another connection to the same remote, to continue with while a request
+    /// runs on a worker, see [`run_worker`]
+    spare: Option<GerritRestApi>,
+}
+
+/// Timeout and retries of requests to the remote, from configuration.
+#[derive(Clone, Copy)]
+struct RequestPolicy {
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 0,
+        }
+    }
+}
+
+/// Get the `remote` command model/schema as a Clap command structure
+pub fn command() -> Command {
+    Command::new("remote")
+        .disable_version_flag(true)
+        .disable_help_flag(true)
+        .disable_help_subcommand(true)
+        .about("Remote commands")
+        .subcommands([
+            Command::new("add")
+                .arg(Arg::new("NAME").required(true))
+                .arg(Arg::new("URL").required(true))
+                .arg(Arg::new("USER").required(true))
+                .about("Add a new remote"),
+            Command::new("list").about("List remotes"),
+            Command::new("show")
+                .arg(Arg::new("NAME"))
+                .about("Show a remote, by default the one in use"),
+            Command::new("use")
+                .arg(Arg::new("NAME").required(true))
+                .about("Switch to another remote"),
+            Command::new("remove")
+                .arg(Arg::new("NAME").required(true))
+                .about("Remove a remote"),
+            command_help(),
+            Command::new("exit").about("Exit from current mode"),
+            Command::new("quit").about("Quit the program"),
+        ])
+}
+
+/// Handle `remote` command.
+pub fn run_command(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    if args.is_empty() {
+        return Ok(CmdAction::EnterMode("remote".to_string()));
+    }
+    let (cmd, cmd_args) = args.split_first().unwrap();
+    match cmd.as_str() {
+        "add" => add_remote(cmd_args),
+        "list" => list_remotes(),
+        "show" => show_remote(cmd_args),
+        "use" => use_remote(cmd_args, gerrit),
+        "remove" => remove_remote(cmd_args),
+        "help" => {
+            print_help(&mut writer, &command(), cmd_args);
+            Ok(CmdAction::Ok)
+        }
+        "exit" => Ok(CmdAction::ExitMode),
+        _ => Err(CmdError::Unhandled),
+    }
+}
+
+/// Connect to a Gerrit server with the given settings.
+/// Returns a printable error message if the connection can't be set up.
+/// SSL certificates are not verified if disabled in settings or with [`set_insecure`].
+pub fn connect(settings: &ConnectionSettings) -> Result<GerritRestApi, String> {
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    let gerrit = open_connection(settings, ctx.insecure)?;
+    ctx.url = settings.url.clone();
+    ctx.settings = Some(settings.clone());
+    ctx.spare = None;
+    Ok(gerrit)
+}
+
+/// Set up a connection with the given settings, see [`connect`].
+fn open_connection(settings: &ConnectionSettings, insecure: bool) -> Result<GerritRestApi, String> {
+    let url = settings
+        .url
+        .parse()
+        .map_err(|err| format!("invalid url '{}': {}", settings.url, err))?;
+    let ssl_verify = settings.ssl_verify && !insecure;
+    GerritRestApi::new(url, &settings.user, &settings.http_password)
+        .and_then(|gerrit| gerrit.ssl_verify(ssl_verify))
+        .map_err(|err| err.to_string())
+}
+
+/// Run a request that reads from the remote on a worker thread, so it can be cancelled
+/// with Ctrl+C in the interactive shell and abandoned when it takes longer than the timeout.
+/// An abandoned request keeps its connection, `gerrit` is left with another one to the same remote.
+/// Requests that timed out, failed with a server error or were rate limited are retried
+/// as many times as configured, waiting twice as long before each retry, or as long as
+/// the server asked to with `Retry-After` when rate limited.
+/// Errors are described as with [`describe_error`].
+pub fn run_request<T, E, F>(gerrit: &mut GerritRestApi, request: F) -> Result<T, CmdError>
+where
+    T: Send + 'static,
+    E: Display + Send + 'static,
+    F: Fn(&mut GerritRestApi) -> Result<T, E> + Send + Sync + 'static,
+{
+    let retries = REMOTE_CONTEXT.lock().borrow().policy.retries;
+    run_attempts(gerrit, request, retries)
+}
+
+/// Run a request that changes something on the remote, like [`run_request`] but never
+/// retried, since the change may have been applied even if the request failed.
+pub fn run_update<T, E, F>(gerrit: &mut GerritRestApi, request: F) -> Result<T, CmdError>
+where
+    T: Send + 'static,
+    E: Display + Send + 'static,
+    F: Fn(&mut GerritRestApi) -> Result<T, E> + Send + Sync + 'static,
+{
+    run_attempts(gerrit, request, 0)
+}
+
+/// Run a request on a worker thread and retry it up to `retries` times, see [`run_request`].
+fn run_attempts<T, E, F>(
+    gerrit: &mut GerritRestApi,
+    request: F,
+    retries: u32,
+) -> Result<T, CmdError>
+where
+    T: Send + 'static,
+    E: Display + Send + 'static,
+    F: Fn(&mut GerritRestApi) -> Result<T, E> + Send + Sync + 'static,
+{
+    let (settings, policy) = {
+        let ctx_guard = REMOTE_CONTEXT.lock();
+        let ctx = ctx_guard.borrow();
+        (ctx.settings.clone(), ctx.policy)
+    };
+    let Some(settings) = settings else {
+        return request(gerrit).map_err(|err| CmdError::Failed(describe_error(&err)));
+    };
+    let request = Arc::new(request);
+    let mut retries_left = retries;
+    let mut backoff = RETRY_BACKOFF;
+    loop {
+        let failure = match run_worker(gerrit, &settings, request.clone(), policy.timeout) {
+            Ok(value) => return Ok(value),
+            Err(failure) => failure,
+        };
+        if retries_left == 0 || !failure.is_transient() {
+            return Err(CmdError::Failed(failure.to_string()));
+        }
+        retries_left -= 1;
+        if wait_cancel(failure.retry_after().unwrap_or(backoff)) {
+            return Err(CmdError::Failed(RequestFailure::Cancelled.to_string()));
+        }
+        backoff *= 2;
+    }
+}
+
+/// Run a single attempt of a request on a worker thread, see [`run_request`].
+fn run_worker<T, E, F>(
+    gerrit: &mut GerritRestApi,
+    settings: &ConnectionSettings,
+    request: Arc<F>,
+    timeout: Duration,
+) -> Result<T, RequestFailure>
+where
+    T: Send + 'static,
+    E: Display + Send + 'static,
+    F: Fn(&mut GerritRestApi) -> Result<T, E> + Send + Sync + 'static,
+{
+    let spare_gerrit = take_spare(settings).map_err(RequestFailure::Error)?;
+    let mut worker_gerrit = std::mem::replace(gerrit, spare_gerrit);
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let result = request(&mut worker_gerrit).map_err(|err| err.to_string());
+        let _ = done_tx.send((worker_gerrit, result));
+    });
+    let deadline = Instant::now() + timeout;
+    loop {
+        match done_rx.try_recv() {
+            Ok((worker_gerrit, result)) => {
+                let spare_gerrit = std::mem::replace(gerrit, worker_gerrit);
+                REMOTE_CONTEXT.lock().borrow_mut().spare = Some(spare_gerrit);
+                return result.map_err(RequestFailure::Error);
+            }
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return Err(RequestFailure::Error(
+                    "request failed unexpectedly".to_string(),
+                ));
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(RequestFailure::TimedOut(timeout));
+        }
+        if wait_cancel(REQUEST_POLL_INTERVAL) {
+            return Err(RequestFailure::Cancelled);
+        }
+    }
+}
+
+/// Take the spare connection to the remote, or connect again when there's none,
+/// which is only the case at first and after a request was abandoned with its connection.
+fn take_spare(settings: &ConnectionSettings) -> Result<GerritRestApi, String> {
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    match ctx.spare.take() {
+        Some(spare) => Ok(spare),
+        None => open_connection(settings, ctx.insecure),
+    }
+}
+
+/// Wait for `duration`, returns early whether the user cancelled with Ctrl+C.
+/// Only the interactive shell can be cancelled.
+fn wait_cancel(duration: Duration) -> bool {
+    if cli::is_interactive() {
+        return cli::wait_ctrl_c(duration);
+    }
+    thread::sleep(duration);
+    false
+}
+
+/// Reason a request to the remote failed, see [`run_request`].
+enum RequestFailure {
+    /// The user pressed Ctrl+C
+    Cancelled,
+    /// No response within the timeout
+    TimedOut(Duration),
+    /// Error returned by the request, or failure to connect again
+    Error(String),
+}
+
+impl RequestFailure {
+    /// Whether the request may succeed if retried.
+    fn is_transient(&self) -> bool {
+        match self {
+            RequestFailure::Cancelled => false,
+            RequestFailure::TimedOut(_) => true,
+            RequestFailure::Error(message) => {
+                let lowercase = message.to_lowercase();
+                is_server_error(&lowercase) || is_rate_limited(&lowercase)
+            }
+        }
+    }
+
+    /// Time the server asked to wait before retrying, if rate limited.
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RequestFailure::Error(message) => retry_after(&message.to_lowercase()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RequestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestFailure::Cancelled => write!(f, "request cancelled"),
+            RequestFailure::TimedOut(timeout) => {
+                let ctx_guard = REMOTE_CONTEXT.lock();
+                let ctx = ctx_guard.borrow();
+                let secs = timeout.as_secs();
+                write!(f, "request to {} timed out after {}s", ctx.url, secs)
+            }
+            RequestFailure::Error(message) => write!(f, "{}", describe_error(message)),
+        }
+    }
+}
+
+/// Get the web URL of a change on the remote connected to.
+pub fn change_url(number: &str) -> String {
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    format!("{}/c/{}", ctx.url.trim_end_matches('/'), number)
+}
+
+/// Set the timeout of requests and how many times to retry them when they time out
+/// or fail with a server error, see [`run_request`].
+pub fn set_request_policy(timeout: Duration, retries: u32) {
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.policy = RequestPolicy { timeout, retries };
+}
+
+/// Skip SSL certificate verification for all connections from now on.
+pub fn set_insecure(insecure: bool) {
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let mut ctx = ctx_guard.borrow_mut();
+    ctx.insecure = insecure;
+}
+
+/// Describe an error from a request to the remote in a readable way.
+/// Common failures like authentication, rate limiting, unreachable server, timeout,
+/// server error and SSL handshake are explained naming the remote, otherwise the error is displayed as is.
+pub fn describe_error(err: &impl Display) -> String {
+    let message = err.to_string();
+    let lowercase = message.to_lowercase();
+    let ctx_guard = REMOTE_CONTEXT.lock();
+    let ctx = ctx_guard.borrow();
+    let is_unauthorized = lowercase.contains("unauthorized")
+        || lowercase
+            .split(|c: char| !c.is_ascii_digit())
+            .any(|code| code == "401");
+    if is_unauthorized {
+        format!(
+            "authentication failed at {}, check user and password",
+            ctx.url
+        )
+    } else if is_rate_limited(&lowercase) {
+        match retry_after(&lowercase) {
+            Some(wait) => format!("rate limited by {}, retry in {}s", ctx.url, wait.as_secs()),
+            None => format!("rate limited by {}, retry later", ctx.url),
+        }
+    } else if lowercase.contains("connection refused")
+        || lowercase.contains("couldn't connect")
+        || lowercase.contains("resolve host")
+    {
+        format!("server {} is unreachable: {}", ctx.url, message)
+    } else if lowercase.contains("timed out") || lowercase.contains("timeout") {
+        format!("request to {} timed out: {}", ctx.url, message)
+    } else if is_server_error(&lowercase) {
+        format!("server error at {}: {}", ctx.url, message)
+    } else if lowercase.contains("ssl") || lowercase.contains("certificate") {
+        format!(
+            "SSL handshake with {} failed: {} (use --insecure to skip certificate verification)",
+            ctx.url, message
+        )
+    } else {
+        message
+    }
+}
+
+/// Check whether a lowercase error message is about a server error, a 5xx HTTP status.
+fn is_server_error(lowercase: &str) -> bool {
+    lowercase.contains("internal server error")
+        || lowercase.contains("bad gateway")
+        || lowercase.contains("service unavailable")
+        || lowercase.contains("gateway timeout")
+        || http_status(lowercase).is_some_and(|status| (500..600).contains(&status))
+}
+
+/// Get the HTTP status in a lowercase error message, a number of 3 digits after
+/// a marker like `status` or `http/1.1`, so other numbers like those of changes
+/// are not taken for it.
+fn http_status(lowercase: &str) -> Option<u16> {
+    let mut words = lowercase
+        .split(|c: char| c.is_whitespace() || c == ':' || c == '=' || c == ',')
+        .filter(|word| !word.is_empty());
+    let mut previous = words.next()?;
+    for word in words {
+        let is_marker = previous.ends_with("status") || previous.starts_with("http");
+        if is_marker && word.len() == 3 {
+            if let Ok(status) = word.parse() {
+                return Some(status);
+            }
+        }
+        previous = word;
+    }
+    None
+}
+
+/// Check whether a lowercase error message is about rate limiting, a 429 HTTP status.
+fn is_rate_limited(lowercase: &str) -> bool {
+    lowercase.contains("too many requests") || http_status(lowercase) == Some(429)
+}
+
+/// Get the time to wait given by the `Retry-After` header in a lowercase error message,
+/// when the header is included and has a number of seconds rather than a date.
+fn retry_after(lowercase: &str) -> Option<Duration> {
+    let (_, after) = lowercase.split_once("retry-after")?;
+    let seconds = after
+        .trim_start_matches(|c: char| c == ':' || c == '=' || c.is_whitespace())
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?;
+    seconds.parse().ok().map(Duration::from_secs)
+}
+
+/// Update the prompt to show the remote in use, unless disabled in configuration.
+pub fn update_prompt(config: &Config) {
+    let remote = config
+        .prompt_remote
+        .unwrap_or(true)
+        .then(|| config.remote.clone())
+        .flatten()
+        .map(theme::faded);
+    cli::set_remote(remote);
+}
+
+/// Print out the list of configured remotes, marking the one in use.
+fn list_remotes() -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let config = load_config()?;
+    if config.remotes.is_empty() {
+        match std::env::var("GERRIT_URL") {
+            Ok(url) => cliprintln!(writer, "remote url: {}", url).unwrap(),
+            Err(_) => cliprintln!(writer, "no remotes configured").unwrap(),
+        }
+        return Ok(CmdAction::Ok);
+    }
+    for (name, remote) in &config.remotes {
+        let in_use = config.remote.as_ref() == Some(name);
+        queue!(
+            writer,
+            Print(if in_use { "* " } else { "  " }),
+            PrintStyled(theme::name(name)),
+            Print("  "),
+            Print(&remote.url),
+            Print("  "),
+            Print(&remote.user),
+            SmartNewLine(1)
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Print out a remote's URL and user, by default the remote in use.
+/// The default remote has no name, only its URL is known once connected.
+fn show_remote(args: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let config = load_config()?;
+    let Some(name) = args.first().or(config.remote.as_ref()) else {
+        let ctx_guard = REMOTE_CONTEXT.lock();
+        let ctx = ctx_guard.borrow();
+        cliprintln!(writer, "default remote url: {}", ctx.url).unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    let Some(remote) = config.remotes.get(name) else {
+        cliprintln!(writer, "No such remote '{}'", name).unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    queue!(writer, PrintStyled(theme::name(name))).unwrap();
+    if config.remote.as_ref() == Some(name) {
+        queue!(writer, Print(" (in use)")).unwrap();
+    }
+    queue!(
+        writer,
+        SmartNewLine(1),
+        Print(format!("  url:  {}", remote.url)),
+        SmartNewLine(1),
+        Print(format!("  user: {}", remote.user)),
+        SmartNewLine(1)
+    )
+    .unwrap();
+    writer.flush().unwrap();
+    Ok(CmdAction::Ok)
+}
+
+/// Add a new remote to the configuration.
+fn add_remote(args: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let [name, url, user] = args else {
+        cliprintln!(writer, "Required NAME URL USER arguments").unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    let mut config = load_config()?;
+    if config.remotes.contains_key(name) {
+        cliprintln!(writer, "Remote '{}' already exists", name).unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    let remote = RemoteConfig {
+        url: url.clone(),
+        user: user.clone(),
+    };
+    config.remotes.insert(name.clone(), remote);
+    save_config(&config)?;
+    Ok(CmdAction::Ok)
+}
+
+/// Switch the connection to another remote and remember it as the remote in use.
+fn use_remote(args: &[String], gerrit: &mut GerritRestApi) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let [name] = args else {
+        cliprintln!(writer, "Required NAME argument").unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    let mut config = load_config()?;
+    let settings = match config.remote_settings(name) {
+        Ok(settings) => settings,
+        Err(err) => {
+            cliprintln!(writer, "Cannot use remote '{}': {}", name, err).unwrap();
+            return Ok(CmdAction::Ok);
+        }
+    };
+    match connect(&settings) {
+        Ok(new_gerrit) => *gerrit = new_gerrit,
+        Err(err) => {
+            cliprintln!(writer, "Failed to connect to '{}': {}", name, err).unwrap();
+            return Ok(CmdAction::Ok);
+        }
+    }
+    config.remote = Some(name.clone());
+    save_config(&config)?;
+    update_prompt(&config);
+    Ok(CmdAction::Ok)
+}
+
+/// Remove a remote from the configuration.
+/// The connection is kept if it's the remote in use, until another remote is selected.
+fn remove_remote(args: &[String]) -> Result<CmdAction, CmdError> {
+    let mut writer = cli::stdout();
+    let [name] = args else {
+        cliprintln!(writer, "Required NAME argument").unwrap();
+        return Ok(CmdAction::Ok);
+    };
+    let mut config = load_config()?;
+    if config.remotes.remove(name).is_none() {
+        cliprintln!(writer, "No such remote '{}'", name).unwrap();
+        return Ok(CmdAction::Ok);
+    }
+    if config.remote.as_ref() == Some(name) {
+        config.remote = None;
+    }
+    save_config(&config)?;
+    update_prompt(&config);
+    Ok(CmdAction::Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::remote::{
+        describe_error, http_status, is_rate_limited, is_server_error, retry_after,
+    };
+
+    #[test]
+    fn test_is_server_error() {
+        assert!(is_server_error("http status 503"));
+        assert!(is_server_error("internal server error"));
+        assert!(!is_server_error("http status 404: not found"));
+        assert!(!is_server_error("change 15032 not found"));
+        assert!(!is_server_error("http status 429: too many requests"));
+        assert!(!is_server_error("change 512 not found"));
+        assert!(!is_server_error("not found: 512"));
+    }
+
+    #[test]
+    fn test_http_status() {
+        assert_eq!(http_status("http status 503"), Some(503));
+        assert_eq!(http_status("status: 502, bad gateway"), Some(502));
+        assert_eq!(http_status("http/1.1 500 internal server error"), Some(500));
+        assert_eq!(http_status("http 404"), Some(404));
+        assert_eq!(http_status("change 512 not found"), None);
+        assert_eq!(http_status("status 5000"), None);
+    }
+
+    #[test]
+    fn test_describe_unauthorized() {
+        assert!(describe_error(&"http status 401").starts_with("authentication failed"));
+        assert!(describe_error(&"Unauthorized").starts_with("authentication failed"));
+        assert_eq!(describe_error(&"Not found: 14012"), "Not found: 14012");
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        assert!(is_rate_limited("http status 429"));
+        assert!(is_rate_limited("too many requests"));
+        assert!(!is_rate_limited("change 14290 not found"));
+        assert!(!is_rate_limited("change 429 not found"));
+        assert_eq!(
+            retry_after("http status 429, retry-after: 30"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(retry_after("retry-after=5s"), Some(Duration::from_secs(5)));
+        assert_eq!(
+            retry_after("retry-after: wed, 21 oct 2026 07:28:00 gmt"),
+            None
+        );
+        assert_eq!(retry_after("http status 429"), None);
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt::Display;
+
+use crossterm::style::{Attribute, Color, ContentStyle, StyledContent};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Theme in use, set from configuration.
+static THEME: Lazy<Mutex<Theme>> = Lazy::new(|| Mutex::new(Theme::default()));
+
+/// Names of the built-in themes, selectable in configuration.
+pub const THEME_NAMES: [&str; 2] = ["default", "mono"];
+
+/// Styles of the pieces of output that are colored, like the prompt and the change lists.
+/// Output is styled through the functions of this module, like [`change_number`],
+/// so each style is decided here once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// Program name at the start of the prompt
+    pub prefix: ContentStyle,
+    /// Symbol at the end of the prompt
+    pub symbol: ContentStyle,
+    /// Change numbers and IDs
+    pub change_number: ContentStyle,
+    /// Other numbers, like patchsets and list indexes
+    pub number: ContentStyle,
+    /// Names of accounts, remotes and saved queries
+    pub name: ContentStyle,
+    /// Secondary details, like owners, topics and elided diff lines
+    pub detail: ContentStyle,
+    /// Less relevant text, like times and hints
+    pub faded: ContentStyle,
+    /// Titles, like file paths over their diff
+    pub heading: ContentStyle,
+    /// Status letter of files changed
+    pub file_status: ContentStyle,
+    /// Positive votes and insertions
+    pub positive: ContentStyle,
+    /// Negative votes and deletions
+    pub negative: ContentStyle,
+    /// Star of changes starred by the account logged in
+    pub star: ContentStyle,
+    pub warning: ContentStyle,
+    /// Messages of failures that are not exceptions
+    pub failure: ContentStyle,
+    pub status_new: ContentStyle,
+    pub status_merged: ContentStyle,
+    pub status_abandoned: ContentStyle,
+    pub status_draft: ContentStyle,
+    /// Exceptions of commands that failed
+    pub error: ContentStyle,
+}
+
+/// The default theme, suited to dark terminals.
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            prefix: ContentStyle::new(),
+            symbol: fg(Color::Green),
+            change_number: fg(Color::DarkYellow),
+            number: fg(Color::Blue),
+            name: fg(Color::Green),
+            detail: fg(Color::Cyan),
+            faded: fg(Color::DarkGrey),
+            heading: with_attribute(Attribute::Bold),
+            file_status: fg(Color::Yellow),
+            positive: fg(Color::Green),
+            negative: fg(Color::Red),
+            star: fg(Color::Yellow),
+            warning: fg(Color::Yellow),
+            failure: fg(Color::Red),
+            status_new: fg(Color::Yellow),
+            status_merged: fg(Color::Green),
+            status_abandoned: fg(Color::Red),
+            status_draft: with_attribute(Attribute::Dim),
+            error: ContentStyle {
+                foreground_color: Some(Color::Black),
+                background_color: Some(Color::Red),
+                ..ContentStyle::new()
+            },
+        }
+    }
+}
+
+impl Theme {
+    /// Get a built-in theme by name, one of [`THEME_NAMES`].
+    /// The `mono` theme has no colors, only attributes like bold,
+    /// for terminals whose palette doesn't go well with the default one.
+    pub fn named(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "mono" => Some(Theme {
+                prefix: ContentStyle::new(),
+                symbol: with_attribute(Attribute::Bold),
+                change_number: ContentStyle::new(),
+                number: ContentStyle::new(),
+                name: ContentStyle::new(),
+                detail: ContentStyle::new(),
+                faded: with_attribute(Attribute::Dim),
+                heading: with_attribute(Attribute::Bold),
+                file_status: ContentStyle::new(),
+                positive: ContentStyle::new(),
+                negative: ContentStyle::new(),
+                star: with_attribute(Attribute::Bold),
+                warning: with_attribute(Attribute::Bold),
+                failure: with_attribute(Attribute::Bold),
+                status_new: with_attribute(Attribute::Bold),
+                status_merged: ContentStyle::new(),
+                status_abandoned: with_attribute(Attribute::Dim),
+                status_draft: with_attribute(Attribute::Dim),
+                error: with_attribute(Attribute::Reverse),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Replace the color of a piece of output, named like the fields of the theme.
+    /// The text of errors keeps its background, so it stands out.
+    pub fn set_color(&mut self, piece: &str, color: Color) -> Result<(), String> {
+        let style = match piece {
+            "prefix" => &mut self.prefix,
+            "symbol" => &mut self.symbol,
+            "change_number" => &mut self.change_number,
+            "number" => &mut self.number,
+            "name" => &mut self.name,
+            "detail" => &mut self.detail,
+            "faded" => &mut self.faded,
+            "heading" => &mut self.heading,
+            "file_status" => &mut self.file_status,
+            "positive" => &mut self.positive,
+            "negative" => &mut self.negative,
+            "star" => &mut self.star,
+            "warning" => &mut self.warning,
+            "failure" => &mut self.failure,
+            "status_new" => &mut self.status_new,
+            "status_merged" => &mut self.status_merged,
+            "status_abandoned" => &mut self.status_abandoned,
+            "status_draft" => &mut self.status_draft,
+            "error" => &mut self.error,
+            _ => return Err(format!("unknown theme color '{}'", piece)),
+        };
+        style.foreground_color = Some(color);
+        Ok(())
+    }
+
+    /// Style a change status by its name with the style of the status,
+    /// or plain if the status has none.
+    pub fn status(&self, name: &str, content: String) -> StyledContent<String> {
+        let style = match name {
+            "NEW" => self.status_new,
+            "MERGED" => self.status_merged,
+            "ABANDONED" => self.status_abandoned,
+            "DRAFT" => self.status_draft,
+            _ => ContentStyle::new(),
+        };
+        style.apply(content)
+    }
+}
+
+/// Style with only a foreground color.
+fn fg(color: Color) -> ContentStyle {
+    ContentStyle {
+        foreground_color: Some(color),
+        ..ContentStyle::new()
+    }
+}
+
+/// Style with only an attribute, no colors.
+fn with_attribute(attribute: Attribute) -> ContentStyle {
+    ContentStyle {
+        attributes: attribute.into(),
+        ..ContentStyle::new()
+    }
+}
+
+/// Set the theme in use, from configuration.
+pub fn set_theme(theme: Theme) {
+    *THEME.lock() = theme;
+}
+
+/// Get the theme in use.
+pub fn current() -> Theme {
+    *THEME.lock()
+}
+
+/// Style content with a style picked from the theme in use.
+fn styled(content: impl Display, style: impl Fn(&Theme) -> ContentStyle) -> StyledContent<String> {
+    style(&current()).apply(content.to_string())
+}
+
+/// Style the program name at the start of the prompt.
+pub fn prefix(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.prefix)
+}
+
+/// Style the symbol at the end of the prompt.
+pub fn symbol(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.symbol)
+}
+
+/// Style a change number or ID.
+pub fn change_number(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.change_number)
+}
+
+/// Style a number other than a change number, like a patchset or a list index.
+pub fn number(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.number)
+}
+
+/// Style the name of an account, a remote or a saved query.
+pub fn name(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.name)
+}
+
+/// Style a secondary detail, like an owner or a topic.
+pub fn detail(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.detail)
+}
+
+/// Style less relevant text, like a time or a hint.
+pub fn faded(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.faded)
+}
+
+/// Style a title, like the path of a file over its diff.
+pub fn heading(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.heading)
+}
+
+/// Style the status letter of a file changed.
+pub fn file_status(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.file_status)
+}
+
+/// Style something positive, like insertions.
+pub fn positive(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.positive)
+}
+
+/// Style something negative, like deletions.
+pub fn negative(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.negative)
+}
+
+/// Style the star of a starred change.
+pub fn star(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.star)
+}
+
+/// Style a vote formatted with its sign, negative or positive by the sign.
+pub fn vote(content: String) -> StyledContent<String> {
+    if content.starts_with('-') {
+        negative(content)
+    } else {
+        positive(content)
+    }
+}
+
+/// Style a warning.
+pub fn warning(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.warning)
+}
+
+/// Style the message of a failure that is not an exception.
+pub fn failure(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.failure)
+}
+
+/// Style a change status by its name, see [`Theme::status`].
+pub fn status(name: &str, content: String) -> StyledContent<String> {
+    current().status(name, content)
+}
+
+/// Style the exception of a command that failed.
+pub fn error(content: impl Display) -> StyledContent<String> {
+    styled(content, |theme| theme.error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::{Attribute, Color};
+
+    use crate::theme::{vote, Theme, THEME_NAMES};
+
+    #[test]
+    fn test_named_themes() {
+        for name in THEME_NAMES {
+            assert!(Theme::named(name).is_some());
+        }
+        assert_eq!(Theme::named("default"), Some(Theme::default()));
+        assert!(Theme::named("nope").is_none());
+        let mono = Theme::named("mono").unwrap();
+        let styles = [mono.prefix, mono.symbol, mono.change_number, mono.error];
+        assert!(styles.iter().all(|style| style.foreground_color.is_none()));
+        assert!(mono.error.attributes.has(Attribute::Reverse));
+    }
+
+    #[test]
+    fn test_set_theme_color() {
+        let mut theme = Theme::default();
+        theme.set_color("symbol", Color::Blue).unwrap();
+        theme.set_color("error", Color::White).unwrap();
+        assert_eq!(theme.symbol.foreground_color, Some(Color::Blue));
+        assert_eq!(theme.error.foreground_color, Some(Color::White));
+        assert_eq!(theme.error.background_color, Some(Color::Red));
+        assert!(theme.set_color("nope", Color::Blue).is_err());
+    }
+
+    #[test]
+    fn test_style_status() {
+        let theme = Theme::default();
+        let status = theme.status("MERGED", "MERGED".to_string());
+        assert_eq!(status.style().foreground_color, Some(Color::Green));
+        let status = theme.status("UNKNOWN", "UNKNOWN".to_string());
+        assert_eq!(status.style().foreground_color, None);
+    }
+
+    #[test]
+    fn test_style_vote() {
+        let theme = Theme::default();
+        assert_eq!(*vote("-1".to_string()).style(), theme.negative);
+        assert_eq!(*vote("+2".to_string()).style(), theme.positive);
+    }
+}
@@ -1,14 +1,42 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::io::{Stdout, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
 
-use clap::{Arg, Command};
-use crossterm::execute;
-use crossterm::style::Print;
+use clap::builder::PossibleValue;
+use clap::{Arg, ArgMatches, Command};
+use crossterm::cursor::MoveToColumn;
+use crossterm::style::{Print, Stylize};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{execute, queue};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use trie_rs::{Trie, TrieBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::cli;
+use crate::cli::SmartNewLine;
+
+/// Function supplying the values of an argument known only at runtime,
+/// like the changes cached from a previous query.
+pub type DynamicValues = fn() -> Vec<PossibleValue>;
+
+/// Style of the loading animation, see [`set_spinner_style`].
+static SPINNER_STYLE: Lazy<Mutex<SpinnerStyle>> = Lazy::new(|| Mutex::new(SpinnerStyle::default()));
+
+/// Time before the loading animation starts, see [`set_spinner_delay`].
+static SPINNER_DELAY: Lazy<Mutex<Duration>> = Lazy::new(|| Mutex::new(Duration::from_millis(1000)));
+
+/// Dynamic values of arguments, indexed by argument id.
+static DYNAMIC_VALUES: Lazy<Mutex<HashMap<String, DynamicValues>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
 /// Trait to add $create related functionally to Trie.
 pub trait TrieUtils {
@@ -47,6 +75,36 @@ pub fn get_command_trie(cmd_app: &Command) -> Trie<u8> {
     builder.build()
 }
 
+/// Prefix trees of the subcommands and argument values of a command schema,
+/// each built the first time it's needed and reused afterwards,
+/// instead of walking the schema again on every key press.
+/// Commands and arguments are told apart by address, which is stable only while
+/// the schema is borrowed, so the tries live no longer than the schema, for lifetime `'a`.
+/// The tries are meant to last for the prompt of a line: the dynamic values of arguments,
+/// like the changes cached from a query, don't change while typing but may once the line runs.
+#[derive(Default)]
+pub struct CompletionTries<'a> {
+    commands: HashMap<*const Command, Trie<u8>>,
+    arg_values: HashMap<*const Arg, Trie<u8>>,
+    schema: PhantomData<&'a Command>,
+}
+
+impl<'a> CompletionTries<'a> {
+    /// Get the prefix tree of the subcommands of a command, see [`get_command_trie`].
+    pub fn command_trie(&mut self, cmd_app: &'a Command) -> &Trie<u8> {
+        self.commands
+            .entry(cmd_app as *const Command)
+            .or_insert_with(|| get_command_trie(cmd_app))
+    }
+
+    /// Get the prefix tree of the possible values of an argument, see [`get_arg_values_trie`].
+    pub fn arg_values_trie(&mut self, arg: &'a Arg) -> &Trie<u8> {
+        self.arg_values
+            .entry(arg as *const Arg)
+            .or_insert_with(|| get_arg_values_trie(arg))
+    }
+}
+
 /// Return a vector of commands based on Command app created with Clap.
 /// One can use the command vector to list all possible commands.
 pub fn get_visible_command_vector(cmd_app: &Command) -> Vec<String> {
@@ -65,7 +123,7 @@ pub fn get_visible_command_vector(cmd_app: &Command) -> Vec<String> {
 /// One can use the command trie to make argument value predictions.
 pub fn get_arg_values_trie(arg: &Arg) -> Trie<u8> {
     let mut builder = TrieBuilder::new();
-    for value in &arg.get_possible_values() {
+    for value in &get_arg_possible_values(arg) {
         for name_alias in value.get_name_and_aliases() {
             builder.push(name_alias);
         }
@@ -77,7 +135,7 @@ pub fn get_arg_values_trie(arg: &Arg) -> Trie<u8> {
 /// One can use the arg value vector to list all possible values.
 pub fn get_arg_values_vector(arg: &Arg) -> Vec<String> {
     let mut vec = Vec::new();
-    for value in &arg.get_possible_values() {
+    for value in &get_arg_possible_values(arg) {
         for name_alias in value.get_name_and_aliases() {
             vec.push(name_alias.to_string());
         }
@@ -85,47 +143,407 @@ pub fn get_arg_values_vector(arg: &Arg) -> Vec<String> {
     vec
 }
 
+/// Return the possible values of an argument to complete its next value, leaving out
+/// the values already given when it takes multiple values, like the terms of a query.
+pub fn remaining_arg_values(arg: &Arg, given: &[String]) -> Vec<String> {
+    let values = get_arg_values_vector(arg);
+    let multiple_values = arg.get_num_args().is_some_and(|num| num.max_values() > 1);
+    if !multiple_values {
+        return values;
+    }
+    values
+        .into_iter()
+        .filter(|value| !given.contains(value))
+        .collect()
+}
+
+/// Find a subcommand of a command by its name or any of its aliases.
+pub fn find_subcommand<'a>(cmd_app: &'a Command, name: &str) -> Option<&'a Command> {
+    cmd_app
+        .get_subcommands()
+        .find(|c| c.get_name() == name || c.get_all_aliases().any(|a| a == name))
+}
+
+/// Return the positional argument of a command taking the value at `index`,
+/// counting only the values given to positional arguments.
+/// A last argument taking multiple values takes all values past its own index.
+pub fn positional_arg(cmd_app: &Command, index: usize) -> Option<&Arg> {
+    let mut last = None;
+    for (i, arg) in cmd_app.get_positionals().enumerate() {
+        if i == index {
+            return Some(arg);
+        }
+        last = Some(arg);
+    }
+    last.filter(|arg| arg.get_num_args().is_some_and(|num| num.max_values() > 1))
+}
+
+/// Return the option of a command given by a word, either as `--long` or as `-s`.
+pub fn find_option<'a>(cmd_app: &'a Command, word: &str) -> Option<&'a Arg> {
+    if let Some(long) = word.strip_prefix("--") {
+        return cmd_app
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long));
+    }
+    let mut chars = word.strip_prefix('-')?.chars();
+    let (Some(short), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    cmd_app
+        .get_arguments()
+        .find(|arg| arg.get_short() == Some(short))
+}
+
+/// Return how many values an option takes in a row at most, none if it's a flag.
+pub fn option_max_values(arg: &Arg) -> usize {
+    if !arg.get_action().takes_values() {
+        return 0;
+    }
+    arg.get_num_args().map_or(1, |num| num.max_values())
+}
+
+/// Hook a function supplying completion values at runtime to the arguments with id `arg_id`.
+/// Unlike static possible values, the argument still accepts any other value.
+pub fn set_dynamic_values(arg_id: &str, values: DynamicValues) {
+    DYNAMIC_VALUES.lock().insert(arg_id.to_string(), values);
+}
+
+/// Return the static possible values of an argument followed by its dynamic values.
+pub fn get_arg_possible_values(arg: &Arg) -> Vec<PossibleValue> {
+    let mut values = arg.get_possible_values();
+    let dynamic_values = DYNAMIC_VALUES.lock().get(arg.get_id().as_str()).copied();
+    if let Some(dynamic_values) = dynamic_values {
+        values.extend(dynamic_values());
+    }
+    values
+}
+
+/// Format seconds since the Unix epoch as a date and time in UTC, like `2024-01-31 13:05:09`.
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // civil date of the days since the epoch, counting years from March so leap days come last
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
 /// Command Action lists actions to taken when returned from command execution
 #[derive(PartialEq)]
 pub enum CmdAction {
     /// OK = no action
     Ok,
-    /// Enter a new CLI mode
+    /// Enter a new CLI mode, with the name shown in the prompt
     EnterMode(String),
+    /// Exit from the current CLI mode back to the previous one
+    ExitMode,
+}
+
+/// A CLI mode entered by the user.
+struct ModeFrame {
+    /// args of the mode, joined before every command typed in it
+    args: Vec<String>,
+    /// name of the mode in the prompt
+    name: String,
+}
+
+/// Stack of the CLI modes entered, the current mode is on top.
+/// No mode is entered at the root of the command tree.
+#[derive(Default)]
+pub struct ModeStack {
+    frames: Vec<ModeFrame>,
+}
+
+impl ModeStack {
+    /// Whether the CLI is at the root, with no mode entered.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Args of the current mode, empty at the root.
+    pub fn args(&self) -> &[String] {
+        self.frames
+            .last()
+            .map_or(&[], |frame| frame.args.as_slice())
+    }
+
+    /// Args of all modes entered, from the first to the current one.
+    pub fn all_args(&self) -> Vec<Vec<String>> {
+        self.frames.iter().map(|frame| frame.args.clone()).collect()
+    }
+
+    /// Enter a new mode with the full `args` down the command tree
+    /// and add its `name` to the prompt.
+    pub fn push(&mut self, args: Vec<String>, name: String) {
+        self.frames.push(ModeFrame { args, name });
+        self.update_prompt();
+    }
+
+    /// Exit the current mode and remove its name from the prompt.
+    /// Returns false if there was no mode to exit from.
+    pub fn pop(&mut self) -> bool {
+        if self.frames.pop().is_none() {
+            return false;
+        }
+        self.update_prompt();
+        true
+    }
+
+    /// Exit all modes back to the root.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.update_prompt();
+    }
+
+    /// Rebuild the modes path shown in the prompt from the stack.
+    fn update_prompt(&self) {
+        let names = self.frames.iter().map(|frame| frame.name.clone().stylize());
+        cli::set_modes(names.collect());
+    }
+}
+
+/// Command Error lists failures returned from command execution
+#[derive(Debug)]
+pub enum CmdError {
+    /// Command is not registered or not handled
+    Unhandled,
+    /// Command failed with a message to display
+    Failed(String),
 }
 
 /// Search down the command schema for the command string input.
 /// The returned command schema corresponds to the last command name in the string.
-pub fn find_command<'a>(cmd_schema: &'a Command, inputs: &[String]) -> &'a Command {
+/// Returns none if any input is not a subcommand, e.g. a command that no longer exists.
+pub fn find_command<'a>(cmd_schema: &'a Command, inputs: &[String]) -> Option<&'a Command> {
     let mut curr_cmd = cmd_schema;
     for input in inputs {
-        let new_cmd = curr_cmd
-            .get_subcommands()
-            .find(|c| c.get_name() == input)
-            .unwrap();
-        curr_cmd = new_cmd;
+        curr_cmd = find_subcommand(curr_cmd, input)?;
+    }
+    Some(curr_cmd)
+}
+
+/// Replace the command names given by alias with the command names in the schema,
+/// so commands can be dispatched by name only, e.g. `c show 1` gives `change show 1`.
+/// The arguments following the last command are kept as given.
+pub fn resolve_aliases(cmd_schema: &Command, inputs: &[String]) -> Vec<String> {
+    let mut resolved = inputs.to_vec();
+    let mut curr_cmd = cmd_schema;
+    for input in resolved.iter_mut() {
+        match find_subcommand(curr_cmd, input) {
+            Some(cmd) => {
+                *input = cmd.get_name().to_string();
+                curr_cmd = cmd;
+            }
+            None => break,
+        }
+    }
+    resolved
+}
+
+/// Parse the arguments of a command given in the prompt against its schema.
+/// The arguments don't include the command name.
+/// A parse error fails with its description, see [`describe_parse_error`].
+pub fn parse_args(cmd_schema: Command, args: &[String]) -> Result<ArgMatches, CmdError> {
+    cmd_schema
+        .no_binary_name(true)
+        .try_get_matches_from(args)
+        .map_err(|err| CmdError::Failed(describe_parse_error(&err)))
+}
+
+/// Check the arguments of a whole command line against the schema of the command it runs,
+/// found down the command tree by the command names leading the line, like `change show 12`.
+/// Malformed commands, like with a missing argument or a value out of range,
+/// fail this way with the same kind of message, before any command runs.
+pub fn validate_args(cmd_schema: &Command, args: &[String]) -> Result<(), CmdError> {
+    let mut curr_cmd = cmd_schema;
+    let mut args = args;
+    while let Some(subcmd) = args.first().and_then(|arg| find_subcommand(curr_cmd, arg)) {
+        curr_cmd = subcmd;
+        args = &args[1..];
     }
-    curr_cmd
+    parse_args(curr_cmd.clone(), args).map(|_| ())
 }
 
-/// Print loading dots until atomic bool is made true.
+/// Describe a parse error in a single line, without the usage and tips that follow,
+/// like `the following required arguments were not provided: <ID>, <ACCOUNT>`.
+pub fn describe_parse_error(err: &clap::Error) -> String {
+    let message = err.to_string();
+    let mut lines = message
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with("Usage:") && !line.starts_with("For more information"))
+        .filter(|line| !line.is_empty() && !line.starts_with("tip:"));
+    let summary = lines
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("error: ");
+    let details: Vec<&str> = lines.collect();
+    if details.is_empty() {
+        summary.to_string()
+    } else {
+        format!("{} {}", summary, details.join(", "))
+    }
+}
+
+/// Print loading dots until the returned [`Loading`] is stopped.
 /// Useful for commands that take time and want to print some loading symbols to terminal meanwhile.
-pub fn loading() -> Arc<AtomicBool> {
-    let loading_done = Arc::new(AtomicBool::new(false));
-    thread::spawn({
-        let this_loading_done = loading_done.clone();
-        move || {
-            let mut writer = cli::stdout();
-            thread::sleep(Duration::from_millis(1000));
-            while !this_loading_done.load(Ordering::SeqCst) {
-                // TODO: BUG: the . dot may be printed just after this_loading_done is set to true
-                // and after the line is cleared.
-                execute!(writer, Print(".")).unwrap();
-                thread::sleep(Duration::from_millis(200));
+/// Nothing is printed when the CLI is not interactive, to keep the output plain,
+/// nor if stopped before the delay set with [`set_spinner_delay`].
+pub fn loading() -> Loading<Stdout> {
+    loading_if(true)
+}
+
+/// Same as [`loading`], but nothing is printed either when not `enabled`,
+/// like for machine-readable output.
+pub fn loading_if(enabled: bool) -> Loading<Stdout> {
+    if !enabled || !cli::is_interactive() {
+        return Loading::none();
+    }
+    let style = *SPINNER_STYLE.lock();
+    let delay = *SPINNER_DELAY.lock();
+    Loading::start(cli::stdout(), style, delay)
+}
+
+/// Set the style of the loading animation, from configuration.
+pub fn set_spinner_style(style: SpinnerStyle) {
+    *SPINNER_STYLE.lock() = style;
+}
+
+/// Set the time before the loading animation starts, from configuration.
+pub fn set_spinner_delay(delay: Duration) {
+    *SPINNER_DELAY.lock() = delay;
+}
+
+/// Style of the loading animation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpinnerStyle {
+    /// A growing line of dots
+    #[default]
+    Dots,
+    /// A braille spinner animated in place
+    Braille,
+    /// A rotating bar animated in place
+    Bar,
+}
+
+impl SpinnerStyle {
+    /// Get the frame number `idx` of the animation, frames repeat in a cycle.
+    fn frame(&self, idx: usize) -> &'static str {
+        let frames: &[&str] = match self {
+            SpinnerStyle::Dots => &["."],
+            SpinnerStyle::Braille => &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+            SpinnerStyle::Bar => &["|", "/", "-", "\\"],
+        };
+        frames[idx % frames.len()]
+    }
+
+    /// Time between frames of the animation.
+    fn interval(&self) -> Duration {
+        match self {
+            SpinnerStyle::Dots => Duration::from_millis(200),
+            SpinnerStyle::Braille | SpinnerStyle::Bar => Duration::from_millis(100),
+        }
+    }
+}
+
+/// Loading dots printed by a background thread, see [`loading`].
+/// The dots are stopped and cleared from the current line with [`Loading::stop`],
+/// or when dropped, like when a command fails early.
+pub struct Loading<W: Write + Send + 'static> {
+    /// Dropping the sender wakes up the thread to stop printing
+    stop_tx: Option<Sender<()>>,
+    /// The thread gives back the writer once it has stopped printing,
+    /// along with whether any frame was printed
+    thread: Option<JoinHandle<(W, bool)>>,
+}
+
+impl<W: Write + Send + 'static> Loading<W> {
+    /// Loading that prints nothing.
+    fn none() -> Self {
+        Self {
+            stop_tx: None,
+            thread: None,
+        }
+    }
+
+    /// Start printing the animation frames to the writer after `delay`.
+    /// Dots are appended to the line, other styles are redrawn in place at the line start.
+    fn start(mut writer: W, style: SpinnerStyle, delay: Duration) -> Self {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut timeout = delay;
+            let mut frame_idx = 0;
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(timeout) {
+                let frame = style.frame(frame_idx);
+                if style == SpinnerStyle::Dots {
+                    execute!(writer, Print(frame)).unwrap();
+                } else {
+                    execute!(writer, MoveToColumn(0), Print(frame)).unwrap();
+                }
+                frame_idx += 1;
+                timeout = style.interval();
             }
+            (writer, frame_idx > 0)
+        });
+        Self {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
         }
-    });
-    loading_done
+    }
+
+    /// Stop the loading dots and clear them from the current line.
+    pub fn stop(mut self) {
+        self.finish();
+    }
+
+    /// Wait for the thread to stop printing before clearing the line,
+    /// otherwise a dot could be printed right after the line is cleared.
+    /// The line is left untouched if stopped before any frame was printed.
+    fn finish(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(thread) = self.thread.take() {
+            if let Ok((mut writer, true)) = thread.join() {
+                execute!(writer, MoveToColumn(0), Clear(ClearType::CurrentLine)).unwrap();
+            }
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for Loading<W> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Print out a value as pretty JSON, for machine-readable output.
+pub fn print_json(writer: &mut impl Write, value: &impl Serialize) -> Result<(), CmdError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|err| CmdError::Failed(format!("Failed to serialize to JSON: {}", err)))?;
+    for line in json.lines() {
+        queue!(writer, Print(line), SmartNewLine(1)).unwrap();
+    }
+    writer.flush().unwrap();
+    Ok(())
 }
 
 /// Find the index where the last occurrence of punctuation or whitespace is found.
@@ -161,9 +579,223 @@ pub fn str_rfind_last_word_separator(str_original: &str) -> usize {
     }
 }
 
+/// A word of an input line, see [`split_words`].
+#[derive(Debug, PartialEq)]
+pub struct InputWord {
+    /// Byte range of the word in the input as typed, quotes included
+    pub span: Range<usize>,
+    /// The word without its quotes and escaping backslashes
+    pub value: String,
+}
+
+/// Split an input line into words separated by whitespace, like a shell does:
+/// text within single or double quotes stays in one word and a backslash escapes
+/// the next character. Within single quotes nothing is escaped, and within double
+/// quotes only `"` and `\` are.
+/// Returns an error if a quote is not closed.
+/// For examples see the test cases
+pub fn split_words(input: &str) -> Result<Vec<InputWord>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<InputWord> = None;
+    let mut chars = input.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c.is_whitespace() {
+            words.extend(word.take());
+            continue;
+        }
+        let curr_word = word.get_or_insert_with(|| InputWord {
+            span: idx..idx,
+            value: String::new(),
+        });
+        match c {
+            '\'' | '"' => loop {
+                match chars.next() {
+                    None => return Err(format!("missing closing quote {}", c)),
+                    Some((_, next)) if next == c => break,
+                    Some((_, '\\'))
+                        if c == '"' && matches!(chars.peek(), Some((_, '"' | '\\'))) =>
+                    {
+                        curr_word.value.push(chars.next().unwrap().1);
+                    }
+                    Some((_, next)) => curr_word.value.push(next),
+                }
+            },
+            '\\' => curr_word
+                .value
+                .push(chars.next().map_or('\\', |(_, next)| next)),
+            _ => curr_word.value.push(c),
+        }
+        curr_word.span.end = chars.peek().map_or(input.len(), |(next_idx, _)| *next_idx);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Find the index where the first word ends, after any leading punctuation or whitespace.
+/// This is the forward counterpart of [`str_rfind_last_word_separator`].
+/// For examples see the test cases
+pub fn str_find_next_word_separator(str: &str) -> usize {
+    let is_separator = |c: char| c.is_ascii_punctuation() || c.is_ascii_whitespace();
+    let word_start = str.find(|c: char| !is_separator(c)).unwrap_or(str.len());
+    str[word_start..]
+        .find(is_separator)
+        .map_or(str.len(), |idx| word_start + idx)
+}
+
+/// Byte index where the grapheme cluster right before `idx` starts.
+/// Returns `None` when `idx` is at the start of the string.
+pub fn prev_grapheme_idx(str: &str, idx: usize) -> Option<usize> {
+    str[..idx]
+        .grapheme_indices(true)
+        .next_back()
+        .map(|(i, _)| i)
+}
+
+/// Byte index right after the grapheme cluster that starts at `idx`.
+/// Returns `None` when `idx` is at the end of the string.
+pub fn next_grapheme_idx(str: &str, idx: usize) -> Option<usize> {
+    str[idx..].graphemes(true).next().map(|g| idx + g.len())
+}
+
+/// Find the longest prefix shared by all the strings.
+/// Returns an empty string when there are no strings.
+pub fn longest_common_prefix(strings: &[String]) -> String {
+    let Some((first, rest)) = strings.split_first() else {
+        return String::new();
+    };
+    rest.iter()
+        .fold(first.as_str(), |prefix, string| {
+            let len = prefix
+                .char_indices()
+                .zip(string.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(prefix.len().min(string.len()), |((idx, _), _)| idx);
+            &prefix[..len]
+        })
+        .to_string()
+}
+
+/// Find the candidates that contain all characters of the input in order, not necessarily
+/// adjacent, e.g. `shw` matches `show`.
+/// Matches are ranked best first: the shorter the span of the matched characters,
+/// then the earlier the match starts, then the shorter the candidate.
+/// Ties keep the order of the candidates.
+pub fn fuzzy_matches(candidates: &[String], input: &str) -> Vec<String> {
+    let mut matches: Vec<((usize, usize, usize), &String)> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            fuzzy_match_span(candidate, input)
+                .map(|(start, end)| ((end - start, start, candidate.len()), candidate))
+        })
+        .collect();
+    matches.sort_by_key(|(rank, _)| *rank);
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// Find the shortest span of characters `(start, end)` in the candidate
+/// that contains all characters of the input in order.
+fn fuzzy_match_span(candidate: &str, input: &str) -> Option<(usize, usize)> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let input: Vec<char> = input.chars().collect();
+    let Some(first) = input.first() else {
+        return Some((0, 0));
+    };
+    let mut best: Option<(usize, usize)> = None;
+    for start in (0..candidate.len()).filter(|&idx| candidate[idx] == *first) {
+        let mut end = start + 1;
+        for c in &input[1..] {
+            match candidate[end..].iter().position(|cc| cc == c) {
+                Some(offset) => end += offset + 1,
+                None => return best,
+            }
+        }
+        if best.map_or(true, |(s, e)| end - start < e - s) {
+            best = Some((start, end));
+        }
+    }
+    best
+}
+
+/// Truncate text to at most `width` terminal columns, ending it with an ellipsis if cut.
+pub fn truncate_text(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        // keep one column for the ellipsis
+        if truncated_width + char_width + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        truncated_width += char_width;
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Wrap a line of text at whitespace into lines of at most `width` characters.
+/// Words longer than the width are kept whole in their own line.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::str_rfind_last_word_separator;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use clap::{Arg, ArgAction, Command};
+    use crossterm::cursor::MoveToColumn;
+    use crossterm::execute;
+    use crossterm::style::Stylize;
+    use crossterm::terminal::{Clear, ClearType};
+    use parking_lot::Mutex;
+
+    use crate::cli;
+    use crate::util::{
+        find_command, find_subcommand, format_timestamp, fuzzy_matches, get_arg_values_trie,
+        get_command_trie, longest_common_prefix, next_grapheme_idx, positional_arg,
+        prev_grapheme_idx, remaining_arg_values, resolve_aliases, split_words,
+        str_find_next_word_separator, str_rfind_last_word_separator, truncate_text, validate_args,
+        wrap_text, CmdError, CompletionTries, Loading, ModeStack, SpinnerStyle, TrieUtils,
+    };
+
+    /// Writer to a buffer that can be read while the writer is owned by a thread.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     fn test1() {
@@ -204,4 +836,351 @@ mod tests {
     fn test8() {
         assert_eq!(str_rfind_last_word_separator("???"), 0);
     }
+
+    #[test]
+    fn test_next_word_separator() {
+        assert_eq!(str_find_next_word_separator("hello world"), 5);
+        assert_eq!(str_find_next_word_separator("  hello"), 7);
+        assert_eq!(str_find_next_word_separator("he.he"), 2);
+        assert_eq!(str_find_next_word_separator(".he.he"), 3);
+        assert_eq!(str_find_next_word_separator(""), 0);
+        assert_eq!(str_find_next_word_separator("???"), 3);
+        assert_eq!(str_find_next_word_separator("日本 語"), 6);
+    }
+
+    #[test]
+    fn test_mode_stack_restores_prefix() {
+        let _state_guard = cli::STATE_TEST_LOCK.lock();
+        let prefix = cli::get_prefix;
+        cli::set_prefix("gerrit".to_string().stylize());
+        let mut modes = ModeStack::default();
+        assert!(modes.args().is_empty());
+        modes.push(vec!["change".to_string()], "change".to_string());
+        modes.push(
+            vec!["change".to_string(), "review".to_string()],
+            "review".to_string(),
+        );
+        assert_eq!(modes.args(), ["change", "review"]);
+        assert_eq!(prefix(), "gerrit/change/review");
+        assert!(modes.pop());
+        assert_eq!(modes.args(), ["change"]);
+        assert_eq!(prefix(), "gerrit/change");
+        assert!(modes.pop());
+        assert!(modes.is_empty());
+        assert_eq!(prefix(), "gerrit");
+        assert!(!modes.pop());
+        assert_eq!(prefix(), "gerrit");
+    }
+
+    #[test]
+    fn test_word_separator_multibyte() {
+        assert_eq!(str_rfind_last_word_separator("日本.語"), 7);
+        assert_eq!(str_rfind_last_word_separator("abc 日本語"), 4);
+        assert_eq!(str_rfind_last_word_separator("ok 👍🏽"), 3);
+        assert_eq!(str_rfind_last_word_separator("👍 "), 0);
+    }
+
+    #[test]
+    fn test_prev_grapheme_idx() {
+        let input = "a日👍🏽";
+        assert_eq!(prev_grapheme_idx(input, input.len()), Some(4));
+        assert_eq!(prev_grapheme_idx(input, 4), Some(1));
+        assert_eq!(prev_grapheme_idx(input, 1), Some(0));
+        assert_eq!(prev_grapheme_idx(input, 0), None);
+        // combining accent is erased together with its base letter
+        assert_eq!(prev_grapheme_idx("cafe\u{301}", 6), Some(3));
+    }
+
+    #[test]
+    fn test_next_grapheme_idx() {
+        let input = "a日👍🏽";
+        assert_eq!(next_grapheme_idx(input, 0), Some(1));
+        assert_eq!(next_grapheme_idx(input, 1), Some(4));
+        assert_eq!(next_grapheme_idx(input, 4), Some(input.len()));
+        assert_eq!(next_grapheme_idx(input, input.len()), None);
+    }
+
+    #[test]
+    fn test_longest_common_prefix_empty() {
+        assert_eq!(longest_common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn test_longest_common_prefix_single() {
+        assert_eq!(longest_common_prefix(&["change".to_string()]), "change");
+    }
+
+    #[test]
+    fn test_longest_common_prefix_many() {
+        let strings = ["query".to_string(), "quit".to_string(), "qu".to_string()];
+        assert_eq!(longest_common_prefix(&strings), "qu");
+    }
+
+    #[test]
+    fn test_longest_common_prefix_none() {
+        let strings = ["change".to_string(), "remote".to_string()];
+        assert_eq!(longest_common_prefix(&strings), "");
+    }
+
+    #[test]
+    fn test_fuzzy_matches_subsequence() {
+        let candidates = ["show".to_string(), "query".to_string(), "help".to_string()];
+        assert_eq!(fuzzy_matches(&candidates, "shw"), vec!["show"]);
+        assert_eq!(fuzzy_matches(&candidates, "qry"), vec!["query"]);
+        assert!(fuzzy_matches(&candidates, "xyz").is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_matches_ranking() {
+        let candidates = ["a-b-c".to_string(), "xabc".to_string(), "abc-d".to_string()];
+        assert_eq!(
+            fuzzy_matches(&candidates, "abc"),
+            vec!["abc-d", "xabc", "a-b-c"]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_matches_tie_breaking() {
+        let candidates = [
+            "remove".to_string(),
+            "remote".to_string(),
+            "rem".to_string(),
+        ];
+        assert_eq!(
+            fuzzy_matches(&candidates, "rm"),
+            vec!["rem", "remove", "remote"]
+        );
+    }
+
+    fn word_values(input: &str) -> Vec<String> {
+        let words = split_words(input).unwrap();
+        words.into_iter().map(|word| word.value).collect()
+    }
+
+    #[test]
+    fn test_split_words_quoted() {
+        assert_eq!(
+            word_values(r#"change comment 123 "fix the thing""#),
+            vec!["change", "comment", "123", "fix the thing"]
+        );
+        assert_eq!(
+            word_values("say 'it is' done"),
+            vec!["say", "it is", "done"]
+        );
+        assert_eq!(word_values(r#"a"b c"d '' x"#), vec!["ab cd", "", "x"]);
+        let words = split_words(r#"  show "a b"  "#).unwrap();
+        assert_eq!(words[0].span, 2..6);
+        assert_eq!(words[1].span, 7..12);
+    }
+
+    #[test]
+    fn test_split_words_escaped() {
+        assert_eq!(word_values(r"fix\ the thing"), vec!["fix the", "thing"]);
+        assert_eq!(word_values(r#""say \"hi\" \n""#), vec![r#"say "hi" \n"#]);
+        assert_eq!(word_values(r"'no \' escape"), vec![r"no \", "escape"]);
+        assert_eq!(word_values(r"trailing\"), vec![r"trailing\"]);
+    }
+
+    #[test]
+    fn test_split_words_unbalanced_quote() {
+        assert!(split_words(r#"comment 1 "fix the"#).is_err());
+        assert!(split_words("comment 1 'fix").is_err());
+        assert!(split_words(r#"comment 1 "fix \""#).is_err());
+    }
+
+    /// Command tree three levels deep, like the `change` command in the prompt.
+    fn completion_schema() -> Command {
+        Command::new("gerrit").subcommand(
+            Command::new("change")
+                .disable_help_subcommand(true)
+                .subcommands([
+                    Command::new("show")
+                        .arg(Arg::new("ID").required(true))
+                        .arg(Arg::new("files").long("files").action(ArgAction::SetTrue)),
+                    Command::new("query").arg(Arg::new("QUERY").num_args(0..)),
+                    Command::new("remove-reviewer")
+                        .arg(Arg::new("ID").required(true))
+                        .arg(Arg::new("ACCOUNT").required(true)),
+                    Command::new("help").alias("?"),
+                ]),
+        )
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let root = completion_schema();
+        let to_args = |line: &str| {
+            line.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        assert!(validate_args(&root, &to_args("change show 12 --files")).is_ok());
+        assert!(validate_args(&root, &to_args("change query")).is_ok());
+        assert!(validate_args(&root, &to_args("change")).is_ok());
+        let failure = |line: &str| match validate_args(&root, &to_args(line)) {
+            Err(CmdError::Failed(message)) => message,
+            _ => panic!("expected '{}' to fail", line),
+        };
+        assert_eq!(
+            failure("change remove-reviewer"),
+            "the following required arguments were not provided: <ID>, <ACCOUNT>"
+        );
+        assert_eq!(
+            failure("change show 12 --nope"),
+            "unexpected argument '--nope' found"
+        );
+        assert_eq!(
+            failure("change show 12 13"),
+            "unexpected argument '13' found"
+        );
+    }
+
+    #[test]
+    fn test_complete_second_level() {
+        let root = completion_schema();
+        let change = find_subcommand(&root, "change").unwrap();
+        let matches = get_command_trie(change).collect_matches(&"sh".to_string());
+        assert_eq!(matches, vec!["show".to_string()]);
+        assert_eq!(find_subcommand(change, "?").unwrap().get_name(), "help");
+        assert!(find_subcommand(change, "sh").is_none());
+    }
+
+    #[test]
+    fn test_completion_tries_reused() {
+        let root = completion_schema();
+        let change = find_subcommand(&root, "change").unwrap();
+        let mut tries = CompletionTries::default();
+        let first: *const _ = tries.command_trie(change);
+        let second: *const _ = tries.command_trie(change);
+        assert_eq!(first, second);
+        let matches = tries
+            .command_trie(change)
+            .collect_matches(&"sh".to_string());
+        assert_eq!(matches, vec!["show".to_string()]);
+        // each command has its own trie
+        let matches = tries.command_trie(&root).collect_matches(&"c".to_string());
+        assert_eq!(matches, vec!["change".to_string()]);
+    }
+
+    #[test]
+    fn test_find_command_unknown() {
+        let root = completion_schema();
+        let to_inputs = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let show = find_command(&root, &to_inputs(&["change", "show"]));
+        assert_eq!(show.unwrap().get_name(), "show");
+        assert!(find_command(&root, &to_inputs(&["change", "nope"])).is_none());
+        assert_eq!(
+            resolve_aliases(&root, &to_inputs(&["change", "?", "show"])),
+            to_inputs(&["change", "help", "show"])
+        );
+        assert!(find_command(&root, &to_inputs(&["nope"])).is_none());
+        assert_eq!(find_command(&root, &[]).unwrap().get_name(), "gerrit");
+    }
+
+    #[test]
+    fn test_complete_third_level() {
+        let root = completion_schema();
+        let change = find_subcommand(&root, "change").unwrap();
+        let show = find_subcommand(change, "show").unwrap();
+        assert_eq!(positional_arg(show, 0).unwrap().get_id(), "ID");
+        assert!(positional_arg(show, 1).is_none());
+        let remove = find_subcommand(change, "remove-reviewer").unwrap();
+        assert_eq!(positional_arg(remove, 1).unwrap().get_id(), "ACCOUNT");
+        let query = find_subcommand(change, "query").unwrap();
+        assert_eq!(positional_arg(query, 3).unwrap().get_id(), "QUERY");
+    }
+
+    #[test]
+    fn test_complete_second_query_term() {
+        let root = completion_schema();
+        let change = find_subcommand(&root, "change").unwrap();
+        let query = find_subcommand(change, "query").unwrap();
+        let terms =
+            Arg::new("TERMS")
+                .num_args(0..)
+                .value_parser(["is:open", "is:wip", "owner:self"]);
+        let given = vec!["is:open".to_string()];
+        // the second word of a query still completes the query terms
+        let arg = positional_arg(query, given.len()).unwrap();
+        assert_eq!(arg.get_id(), "QUERY");
+        assert_eq!(
+            remaining_arg_values(&terms, &given),
+            vec!["is:wip", "owner:self"]
+        );
+        let matches = get_arg_values_trie(&terms).collect_matches(&"is:".to_string());
+        assert_eq!(matches, vec!["is:open", "is:wip"]);
+        // a single value argument is offered all its values
+        let status = Arg::new("STATUS").value_parser(["open", "closed"]);
+        assert_eq!(
+            remaining_arg_values(&status, &["open".to_string()]),
+            vec!["open", "closed"]
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1361445396), "2013-02-21 11:16:36");
+        assert_eq!(format_timestamp(1709164800), "2024-02-29 00:00:00");
+        assert_eq!(format_timestamp(1709251199), "2024-02-29 23:59:59");
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("Fix typo", 10), "Fix typo");
+        assert_eq!(truncate_text("Fix typo in header", 10), "Fix typo …");
+        assert_eq!(truncate_text("日本語の件名", 7), "日本語…");
+        assert_eq!(truncate_text("abc", 0), "…");
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        assert_eq!(
+            wrap_text("looks good to me", 10),
+            vec!["looks good", "to me"]
+        );
+        assert_eq!(
+            wrap_text("a verylongword b", 4),
+            vec!["a", "verylongword", "b"]
+        );
+        assert_eq!(wrap_text("", 10), vec![""]);
+    }
+
+    #[test]
+    fn test_loading_stop_leaves_no_dots() {
+        let mut clear = Vec::new();
+        execute!(clear, MoveToColumn(0), Clear(ClearType::CurrentLine)).unwrap();
+        for i in 0..100 {
+            let buffer = SharedBuffer::default();
+            let loading = Loading::start(buffer.clone(), SpinnerStyle::Dots, Duration::ZERO);
+            thread::sleep(Duration::from_micros(i % 7 * 50));
+            loading.stop();
+            // nothing is printed after the line is cleared
+            thread::sleep(Duration::from_micros(100));
+            let output = buffer.0.lock();
+            if output.is_empty() {
+                continue;
+            }
+            assert!(output.ends_with(&clear));
+            assert!(output[..output.len() - clear.len()]
+                .iter()
+                .all(|&c| c == b'.'));
+        }
+    }
+
+    #[test]
+    fn test_loading_stopped_before_delay_prints_nothing() {
+        let buffer = SharedBuffer::default();
+        let loading = Loading::start(buffer.clone(), SpinnerStyle::Bar, Duration::from_secs(60));
+        loading.stop();
+        assert!(buffer.0.lock().is_empty());
+    }
+
+    #[test]
+    fn test_spinner_frames_cycle() {
+        assert_eq!(SpinnerStyle::Dots.frame(3), ".");
+        assert_eq!(SpinnerStyle::Bar.frame(1), "/");
+        assert_eq!(SpinnerStyle::Bar.frame(4), "|");
+        assert_eq!(SpinnerStyle::Braille.frame(10), "⠋");
+    }
 }